@@ -0,0 +1,166 @@
+use std::f32::consts::PI;
+use std::hint::black_box;
+use std::sync::Arc;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use rayon::iter::{IntoParallelIterator as _, ParallelIterator as _};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use spectral::audio::{AudioData, Channel};
+use spectral::spectrogram::Spectrogram;
+
+const SAMPLE_RATE: u32 = 44100;
+const DURATION_SECS: usize = 60;
+
+/// A minute of deterministic stereo noise with a few tones mixed in
+fn test_audio() -> AudioData {
+	let mut state = 0x2545f491_u32;
+	let samples = (0..SAMPLE_RATE as usize * DURATION_SECS * 2)
+		.map(|i| {
+			state ^= state << 13;
+			state ^= state >> 17;
+			state ^= state << 5;
+
+			let t = (i / 2) as f32 / SAMPLE_RATE as f32;
+			let noise = state as f32 / u32::MAX as f32 - 0.5;
+			let tone = (t * 440. * std::f32::consts::TAU).sin()
+				+ (t * 3520. * std::f32::consts::TAU).sin();
+
+			noise * 0.2 + tone * 0.3
+		})
+		.collect();
+
+	AudioData::from_samples(0, samples, SAMPLE_RATE, 2)
+}
+
+/// The STFT as it was before the real-input FFT, for comparison: a complex FFT
+/// with a freshly allocated buffer per column
+struct ComplexSpectrogram {
+	fft_size: usize,
+	window: Vec<f32>,
+	fft: Arc<dyn Fft<f32>>,
+}
+
+impl ComplexSpectrogram {
+	fn new(fft_size: usize) -> Self {
+		Self {
+			fft_size,
+			window: (0..fft_size)
+				.map(|i| 0.5 * (1. - (2. * PI * i as f32 / (fft_size - 1) as f32).cos()))
+				.collect(),
+			fft: FftPlanner::new().plan_fft_forward(fft_size),
+		}
+	}
+
+	fn compute_column(&self, samples: &[f32], center_sample: isize) -> Vec<f32> {
+		let half = (self.fft_size / 2) as isize;
+		let mut buffer: Vec<_> = (0..self.fft_size)
+			.map(|i| {
+				let idx = center_sample - half + i as isize;
+				let sample = if idx >= 0 && (idx as usize) < samples.len() {
+					samples[idx as usize]
+				} else {
+					0.
+				};
+				Complex::new(sample * self.window[i], 0.)
+			})
+			.collect();
+
+		self.fft.process(&mut buffer);
+
+		buffer[..self.fft_size / 2]
+			.iter()
+			.map(|c| 20. * (c.norm() * 2. / self.fft_size as f32).max(1e-10).log10())
+			.collect()
+	}
+
+	fn compute_columns(
+		&self,
+		data: &AudioData,
+		channel: Channel,
+		start_time: f64,
+		ms_per_column: f64,
+		columns: usize,
+	) -> Vec<Vec<f32>> {
+		let samples = data.channel_samples(channel);
+
+		(0..columns)
+			.into_par_iter()
+			.map(|i| {
+				let center = start_time + (i as f64 + 0.5) * ms_per_column;
+				let sample = (center / 1000. * data.sample_rate as f64) as isize;
+				self.compute_column(&samples, sample)
+			})
+			.collect()
+	}
+}
+
+fn compute_columns(c: &mut Criterion) {
+	let data = test_audio();
+	// Warm up the lazily derived channel
+	data.channel_samples(Channel::Left);
+
+	let mut group = c.benchmark_group("compute_columns");
+
+	for fft_size in [1024, 4096] {
+		let spectrogram = Spectrogram::new(fft_size);
+		let baseline = ComplexSpectrogram::new(fft_size);
+
+		// One tile at 128 pixels per second, and a whole-song overview
+		for (name, ms_per_column, columns) in [
+			("tile", 1000. / 128., 256),
+			("overview", 60000. / 2048., 2048),
+		] {
+			group.bench_with_input(BenchmarkId::new(name, fft_size), &fft_size, |b, _| {
+				b.iter(|| {
+					black_box(spectrogram.compute_columns(
+						&data,
+						Channel::Left,
+						0.,
+						ms_per_column,
+						columns,
+					))
+				})
+			});
+
+			group.bench_with_input(
+				BenchmarkId::new(format!("{}_complex", name), fft_size),
+				&fft_size,
+				|b, _| {
+					b.iter(|| {
+						black_box(baseline.compute_columns(
+							&data,
+							Channel::Left,
+							0.,
+							ms_per_column,
+							columns,
+						))
+					})
+				},
+			);
+		}
+
+		// Tiles are rendered straight into a reused buffer
+		let mut buffer = vec![0.; 256 * spectrogram.bins()];
+		group.bench_with_input(
+			BenchmarkId::new("tile_into", fft_size),
+			&fft_size,
+			|b, _| {
+				b.iter(|| {
+					spectrogram.compute_columns_into(
+						&data,
+						Channel::Left,
+						0.,
+						1000. / 128.,
+						black_box(&mut buffer),
+					)
+				})
+			},
+		);
+	}
+
+	group.finish();
+}
+
+criterion_group!(benches, compute_columns);
+criterion_main!(benches);
@@ -0,0 +1,281 @@
+use egui::{Pos2, Rect, Stroke, Ui};
+
+use crate::app::SpectralApp;
+use crate::app::history::EditHistoryEntry;
+use crate::app::timing::GRAB_DISTANCE;
+use crate::colors::COLOR_BEAT_MARKER;
+use crate::tempo_map::fit_tempo_map;
+use crate::timing::section_index;
+
+// Markers further than this from any onset are left alone when snapping
+const ONSET_SNAP_MS: f64 = 40.;
+
+// Markers closer than this to an existing one are not placed
+const MIN_MARKER_GAP_MS: f64 = 1.;
+
+/// Beats placed one by one for music without a steady tempo, turned into timing
+/// points once they are all in
+pub struct BeatMarkers {
+	markers: Vec<f64>,
+	per_measure: bool,
+	beats_per_measure: u32,
+	tolerance_bpm: f64,
+}
+
+impl Default for BeatMarkers {
+	fn default() -> Self {
+		Self {
+			markers: vec![],
+			per_measure: false,
+			beats_per_measure: 4,
+			tolerance_bpm: 1.,
+		}
+	}
+}
+
+impl BeatMarkers {
+	fn insert(&mut self, ms: f64) {
+		let idx = self.markers.partition_point(|&m| m < ms);
+		let neighbours = idx.saturating_sub(1)..(idx + 1).min(self.markers.len());
+
+		if !self.markers[neighbours]
+			.iter()
+			.any(|&m| (m - ms).abs() < MIN_MARKER_GAP_MS)
+		{
+			self.markers.insert(idx, ms);
+		}
+	}
+}
+
+impl SpectralApp {
+	/// Places a marker at `ms`, or removes the one under `x` if there is one
+	pub fn click_beat_marker(&mut self, ms: f64, x: f32, rect: Rect) {
+		let Some(beat_markers) = &mut self.beat_markers else {
+			return;
+		};
+
+		let grabbed = beat_markers
+			.markers
+			.iter()
+			.position(|&m| (self.timeline.ms_to_x(m, rect) - x).abs() <= GRAB_DISTANCE);
+
+		match grabbed {
+			Some(idx) => {
+				beat_markers.markers.remove(idx);
+			},
+			None => beat_markers.insert(ms.round()),
+		}
+	}
+
+	/// B drops a marker at the playhead, for tapping along during playback
+	pub fn handle_beat_marker_keys(&mut self, ctx: &egui::Context) {
+		if ctx.wants_keyboard_input() || !ctx.input(|i| i.key_pressed(egui::Key::B)) {
+			return;
+		}
+
+		let position = self.audio_player.get_audible_position_ms().round();
+		if let Some(beat_markers) = &mut self.beat_markers {
+			beat_markers.insert(position);
+		}
+	}
+
+	fn snap_beat_markers_to_onsets(&mut self) {
+		let Some(beat_markers) = &mut self.beat_markers else {
+			return;
+		};
+		let Some(onsets) = &self.onsets else {
+			return;
+		};
+
+		for marker in &mut beat_markers.markers {
+			if let Some(&peak) = onsets
+				.peaks_in(*marker - ONSET_SNAP_MS, *marker + ONSET_SNAP_MS)
+				.iter()
+				.min_by(|a, b| (*a - *marker).abs().total_cmp(&(*b - *marker).abs()))
+			{
+				*marker = peak.round();
+			}
+		}
+
+		beat_markers.markers.sort_by(f64::total_cmp);
+		// Neighbouring markers may have snapped to the same onset
+		beat_markers
+			.markers
+			.dedup_by(|a, b| (*a - *b).abs() < MIN_MARKER_GAP_MS);
+	}
+
+	/// Replaces the timing points between the first and last marker by ones fitted
+	/// to the markers, recorded as a single history entry. The tempo in force at the
+	/// last marker is restored there, so the beats after it stay in place.
+	fn generate_tempo_map(&mut self) {
+		let Some(beat_markers) = &self.beat_markers else {
+			return;
+		};
+
+		let fitted = fit_tempo_map(
+			&beat_markers.markers,
+			beat_markers.beats_per_measure,
+			beat_markers.per_measure,
+			beat_markers.tolerance_bpm,
+		);
+		// Keep the markers around rather than deleting points for nothing
+		let (false, Some(&first), Some(&last)) = (
+			fitted.is_empty(),
+			beat_markers.markers.first(),
+			beat_markers.markers.last(),
+		) else {
+			return;
+		};
+		let marker_count = beat_markers.markers.len();
+		self.beat_markers = None;
+
+		let mut timing_points = self.timing_points.write().unwrap();
+
+		// Starts the section again from the tempo it had at the last marker
+		let restored = section_index(&timing_points, last)
+			.filter(|&i| timing_points[i].offset <= last)
+			.map(|i| {
+				let tp = &timing_points[i];
+				let mut restored = tp.duplicate_at(last);
+				restored.bpm = tp.bpm_at(last, timing_points.get(i + 1));
+				restored
+			});
+
+		let replaced: Vec<_> = timing_points
+			.iter()
+			.filter(|tp| (first..=last).contains(&tp.offset))
+			.copied()
+			.collect();
+		timing_points.retain(|tp| !(first..=last).contains(&tp.offset));
+		timing_points.extend(fitted.iter().copied());
+		timing_points.extend(restored);
+		drop(timing_points);
+
+		self.sort_timing_points();
+
+		self.history.begin(format!(
+			"Generate {} timing points from {} beat markers",
+			fitted.len(),
+			marker_count
+		));
+		for tp in replaced {
+			self.history.push(EditHistoryEntry::DeleteTimingPoint(tp));
+		}
+		for tp in fitted.into_iter().chain(restored) {
+			self.history.push(EditHistoryEntry::CreateTimingPoint(tp));
+		}
+		self.history.commit();
+	}
+
+	pub fn draw_beat_markers(&self, ui: &mut Ui, rect: Rect) {
+		let Some(beat_markers) = &self.beat_markers else {
+			return;
+		};
+
+		let painter = ui.painter_at(rect);
+		let beats_per_measure = beat_markers.beats_per_measure.max(1) as usize;
+
+		for (i, &marker) in beat_markers.markers.iter().enumerate() {
+			let x = self.timeline.ms_to_x(marker, rect);
+			if x < rect.left() || x > rect.right() {
+				continue;
+			}
+
+			let downbeat = i % beats_per_measure == 0;
+			painter.line_segment(
+				[Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+				Stroke::new(if downbeat { 2. } else { 1. }, COLOR_BEAT_MARKER),
+			);
+
+			if downbeat {
+				painter.text(
+					Pos2::new(x + 3., rect.top() + 3.),
+					egui::Align2::LEFT_TOP,
+					format!("{}", i / beats_per_measure + 1),
+					egui::FontId::proportional(10.),
+					COLOR_BEAT_MARKER,
+				);
+			}
+		}
+	}
+
+	pub fn draw_beat_marker_tools(&mut self, ui: &mut Ui) {
+		let Some(beat_markers) = &mut self.beat_markers else {
+			if ui
+				.button("Place beat markers")
+				.on_hover_text(
+					"Mark every beat by hand, then fit timing points to them. \
+					 For music without a steady tempo.",
+				)
+				.clicked()
+			{
+				self.beat_markers = Some(Default::default());
+			}
+			return;
+		};
+
+		ui.label(format!(
+			"{} beat markers. Click the timeline to place or remove one, \
+			 press B to place one at the playhead.",
+			beat_markers.markers.len()
+		));
+
+		ui.horizontal(|ui| {
+			ui.radio_value(&mut beat_markers.per_measure, false, "Point per beat");
+			ui.radio_value(&mut beat_markers.per_measure, true, "Point per measure");
+		});
+
+		ui.horizontal(|ui| {
+			ui.label("Beats per measure");
+			ui.add(egui::DragValue::new(&mut beat_markers.beats_per_measure).range(1..=16));
+		});
+
+		ui.horizontal(|ui| {
+			ui.label("Merge within");
+			ui.add(
+				egui::DragValue::new(&mut beat_markers.tolerance_bpm)
+					.range(0.0..=20.0)
+					.speed(0.05)
+					.suffix(" BPM"),
+			)
+			.on_hover_text("Neighbouring sections closer than this in BPM become one");
+		});
+
+		let can_generate = beat_markers.markers.len() >= 2;
+		let mut snap = false;
+		let mut generate = false;
+		let mut cancel = false;
+
+		ui.horizontal(|ui| {
+			if ui
+				.add_enabled(self.onsets.is_some(), egui::Button::new("Snap to onsets"))
+				.clicked()
+			{
+				snap = true;
+			}
+
+			if ui
+				.add_enabled(can_generate, egui::Button::new("Generate"))
+				.clicked()
+			{
+				generate = true;
+			}
+
+			if ui.button("Cancel").clicked() {
+				cancel = true;
+			}
+		});
+
+		if snap {
+			self.snap_beat_markers_to_onsets();
+		}
+
+		if generate {
+			self.generate_tempo_map();
+		}
+
+		if cancel {
+			self.beat_markers = None;
+		}
+	}
+}
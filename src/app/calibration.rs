@@ -0,0 +1,182 @@
+use std::time::Instant;
+
+use crate::app::SpectralApp;
+use crate::metronome::click_track;
+
+const CALIBRATION_INTERVAL_MS: f64 = 1000.;
+const CALIBRATION_BEATS: usize = 24;
+const CALIBRATION_MIN_TAPS: usize = 8;
+
+// Taps up to this long before a click are counted as early taps on that click
+const CALIBRATION_EARLY_MS: f64 = 200.;
+
+// Taps further than this from the median are missed beats and left out
+const CALIBRATION_OUTLIER_MS: f64 = 60.;
+
+/// Measures output latency as the delay between clicks being queued and the
+/// user tapping along to them
+#[derive(Default)]
+pub struct LatencyCalibration {
+	started: Option<Instant>,
+	offsets: Vec<f64>,
+}
+
+impl LatencyCalibration {
+	fn tap(&mut self) {
+		let Some(started) = self.started else {
+			return;
+		};
+
+		let elapsed = started.elapsed().as_secs_f64() * 1000.;
+		if elapsed > CALIBRATION_BEATS as f64 * CALIBRATION_INTERVAL_MS {
+			return;
+		}
+
+		let offset = (elapsed + CALIBRATION_EARLY_MS).rem_euclid(CALIBRATION_INTERVAL_MS)
+			- CALIBRATION_EARLY_MS;
+		self.offsets.push(offset);
+	}
+
+	fn is_running(&self) -> bool {
+		self.started.is_some_and(|started| {
+			started.elapsed().as_secs_f64() * 1000.
+				< CALIBRATION_BEATS as f64 * CALIBRATION_INTERVAL_MS
+		})
+	}
+
+	/// Median tap offset and the spread between the quartiles, ignoring taps far
+	/// from the median such as those while the user finds the beat
+	fn estimate(&self) -> Option<(f64, f64)> {
+		let mut offsets = self.offsets.clone();
+		offsets.sort_by(f64::total_cmp);
+		let median = *offsets.get(offsets.len() / 2)?;
+
+		offsets.retain(|offset| (offset - median).abs() <= CALIBRATION_OUTLIER_MS);
+		if offsets.len() < CALIBRATION_MIN_TAPS {
+			return None;
+		}
+
+		let quantile = |q: f64| offsets[((offsets.len() - 1) as f64 * q).round() as usize];
+
+		Some((quantile(0.5), quantile(0.75) - quantile(0.25)))
+	}
+}
+
+impl SpectralApp {
+	fn start_latency_calibration(&mut self) {
+		self.audio_player.pause();
+
+		match click_track(CALIBRATION_BEATS, CALIBRATION_INTERVAL_MS) {
+			Ok(track) => {
+				self.audio_player.metronome_sink.skip_one();
+				self.audio_player.metronome_sink.append(track);
+
+				self.latency_calibration = Some(LatencyCalibration {
+					started: Some(Instant::now()),
+					offsets: vec![],
+				});
+			},
+			Err(e) => self.set_result(format!("Error during calibration: {:?}", e)),
+		}
+	}
+
+	fn stop_latency_calibration(&mut self) {
+		if self
+			.latency_calibration
+			.as_ref()
+			.is_some_and(LatencyCalibration::is_running)
+		{
+			self.audio_player.metronome_sink.skip_one();
+		}
+		self.latency_calibration = None;
+	}
+
+	pub fn draw_calibration_window(&mut self, ctx: &egui::Context) {
+		let Some(calibration) = &mut self.latency_calibration else {
+			return;
+		};
+
+		let running = calibration.is_running();
+		if running {
+			ctx.request_repaint();
+
+			if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Space)) {
+				calibration.tap();
+			}
+		}
+
+		let estimate = calibration.estimate();
+		let taps = calibration.offsets.len();
+
+		let mut open = true;
+		let mut start = false;
+
+		egui::Window::new("Latency calibration")
+			.open(&mut open)
+			.resizable(false)
+			.collapsible(false)
+			.show(ctx, |ui| {
+				ui.label(
+					"Start the click track and tap in time with it using Space or the button \
+					 below. The clicks are steady, so tap on them as you would play along \
+					 rather than reacting to each one.",
+				);
+
+				ui.separator();
+
+				ui.horizontal(|ui| {
+					if ui
+						.button(if running { "Restart" } else { "Start" })
+						.clicked()
+					{
+						start = true;
+					}
+
+					if ui
+						.add_enabled(
+							running,
+							egui::Button::new("Tap").min_size(egui::vec2(80., 0.)),
+						)
+						.clicked() && let Some(calibration) = &mut self.latency_calibration
+					{
+						calibration.tap();
+					}
+				});
+
+				ui.label(format!("{} taps", taps));
+
+				match estimate {
+					Some((latency, spread)) => {
+						ui.label(format!(
+							"Measured latency: {:.0} ms (±{:.0} ms)",
+							latency,
+							spread / 2.
+						));
+
+						if ui.button("Apply").clicked() {
+							let latency = latency.round();
+							self.audio_player.set_output_latency(latency);
+							self.settings.write(move |s| s.output_latency_ms = latency);
+						}
+					},
+					None => {
+						ui.label(format!(
+							"At least {} taps close to the beat are needed",
+							CALIBRATION_MIN_TAPS
+						));
+					},
+				}
+
+				ui.label(format!(
+					"Current latency: {:.0} ms",
+					self.audio_player.get_output_latency()
+				));
+			});
+
+		if start {
+			self.start_latency_calibration();
+		} else if !open {
+			self.stop_latency_calibration();
+		}
+	}
+}
@@ -0,0 +1,113 @@
+use crate::app::SpectralApp;
+use crate::export::export_spectrogram_image;
+use crate::render::RenderOptions;
+use crate::widgets::time::TimeInput;
+
+impl SpectralApp {
+	fn visible_export_range(&self) -> (f64, f64) {
+		let (start, end) = self.timeline.visible_range(self.timeline_width);
+		let end = self
+			.audio_data
+			.as_ref()
+			.map_or(end, |data| end.min(data.duration));
+
+		(start, end)
+	}
+
+	pub fn open_image_export(&mut self) {
+		let (start, end) = self.visible_export_range();
+		self.image_export = Some(RenderOptions::new(start, end, 1920, 1080));
+	}
+
+	pub fn draw_image_export_window(&mut self, ctx: &egui::Context) {
+		let Some(mut options) = self.image_export.take() else {
+			return;
+		};
+
+		let mut open = true;
+		let mut export = false;
+
+		egui::Window::new("Export image")
+			.open(&mut open)
+			.resizable(false)
+			.collapsible(false)
+			.show(ctx, |ui| {
+				egui::Grid::new("image_export")
+					.num_columns(2)
+					.show(ui, |ui| {
+						ui.label("Start:");
+						TimeInput::ui(
+							ui,
+							&mut options.start_ms,
+							egui::Id::new("image_export_start"),
+						);
+						ui.end_row();
+
+						ui.label("End:");
+						TimeInput::ui(ui, &mut options.end_ms, egui::Id::new("image_export_end"));
+						ui.end_row();
+
+						ui.label("Resolution:");
+						ui.horizontal(|ui| {
+							ui.add(egui::DragValue::new(&mut options.width).range(64..=16384));
+							ui.label("x");
+							ui.add(egui::DragValue::new(&mut options.height).range(64..=16384));
+						});
+						ui.end_row();
+					});
+
+				ui.horizontal(|ui| {
+					if ui.button("Visible range").clicked() {
+						(options.start_ms, options.end_ms) = self.visible_export_range();
+					}
+
+					if ui.button("Whole track").clicked()
+						&& let Some(data) = &self.audio_data
+					{
+						options.start_ms = 0.;
+						options.end_ms = data.duration;
+					}
+				});
+
+				ui.separator();
+
+				ui.checkbox(&mut options.beat_ticks, "Beat ticks");
+				ui.checkbox(&mut options.timing_points, "Timing points");
+				ui.checkbox(&mut options.frequency_axis, "Frequency axis");
+				ui.checkbox(&mut options.time_ruler, "Time ruler");
+
+				ui.separator();
+
+				ui.label("Uses the current FFT size, channels, view, colormap and dB range");
+
+				if ui
+					.add_enabled(
+						options.end_ms > options.start_ms,
+						egui::Button::new("Export"),
+					)
+					.clicked()
+				{
+					export = true;
+				}
+			});
+
+		if export && let Some(data) = self.audio_data.clone() {
+			options.fft_size = self.fft_size;
+			options.channel_source = self.channel_source;
+			options.view = self.spectrogram_view;
+			options.palette = self.spectrogram_palette.clone();
+			(options.min_db, options.max_db) = self.db_range();
+			options.whitening = self.spectrogram_whitening();
+			options.snap_divisor = self.snap_divisor;
+
+			export_spectrogram_image(
+				data,
+				self.timing_points.read().unwrap().clone(),
+				options,
+				self.event_tx.clone(),
+			);
+		} else if open {
+			self.image_export = Some(options);
+		}
+	}
+}
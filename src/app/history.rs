@@ -3,10 +3,7 @@ use std::fmt::Display;
 use crate::app::SpectralApp;
 use crate::timing::TimingPoint;
 
-const MAX_HISTORY_CAPACITY: usize = 200;
-
-#[allow(clippy::enum_variant_names)]
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum EditHistoryEntry {
 	CreateTimingPoint(TimingPoint),
 	DeleteTimingPoint(TimingPoint),
@@ -14,6 +11,11 @@ pub enum EditHistoryEntry {
 		before: TimingPoint,
 		after: TimingPoint,
 	},
+	/// Several edits that are undone and redone together
+	Batch {
+		name: String,
+		entries: Vec<EditHistoryEntry>,
+	},
 }
 
 impl Display for EditHistoryEntry {
@@ -26,7 +28,18 @@ impl Display for EditHistoryEntry {
 					write!(f, "Change BPM {:.02} -> {:.02}", before.bpm, after.bpm)
 				} else if before.offset != after.offset {
 					write!(f, "Change offset {} -> {}", before.offset, after.offset)
-				} else {
+				} else if before.ramp != after.ramp {
+					write!(f, "Change tempo ramp {} -> {}", before.ramp, after.ramp)
+				} else if before.swing != after.swing || before.swing_unit != after.swing_unit {
+					write!(
+						f,
+						"Change swing {:.0}% {} -> {:.0}% {}",
+						before.swing * 100.,
+						before.swing_unit,
+						after.swing * 100.,
+						after.swing_unit
+					)
+				} else if before.signature != after.signature {
 					write!(
 						f,
 						"Change signature {}/{} -> {}/{}",
@@ -35,30 +48,93 @@ impl Display for EditHistoryEntry {
 						after.signature.0,
 						after.signature.1
 					)
+				} else if before.grouping != after.grouping {
+					write!(
+						f,
+						"Change grouping {} -> {}",
+						before.grouping_text(),
+						after.grouping_text()
+					)
+				} else {
+					write!(f, "Edit timing point")
 				}
 			},
+			Self::Batch { name, .. } => write!(f, "{}", name),
 		}
 	}
 }
 
-#[derive(Default)]
 pub struct EditHistory {
 	changes: Vec<EditHistoryEntry>,
 	cursor: usize,
+	capacity: usize,
+	transaction: Option<(String, Vec<EditHistoryEntry>)>,
 }
 
 impl EditHistory {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			changes: vec![],
+			cursor: 0,
+			capacity: capacity.max(1),
+			transaction: None,
+		}
+	}
+
 	pub fn push(&mut self, entry: EditHistoryEntry) {
+		if let Some((_, entries)) = &mut self.transaction {
+			entries.push(entry);
+			return;
+		}
+
 		self.changes.truncate(self.cursor);
 
 		self.changes.push(entry);
+		self.trim();
 
-		if self.changes.len() > MAX_HISTORY_CAPACITY {
-			self.changes
-				.drain(0..self.changes.len() - MAX_HISTORY_CAPACITY);
+		self.cursor = self.changes.len();
+	}
+
+	/// Drops the oldest entries beyond the capacity
+	fn trim(&mut self) {
+		if self.changes.len() > self.capacity {
+			let excess = self.changes.len() - self.capacity;
+			self.changes.drain(0..excess);
+			self.cursor = self.cursor.saturating_sub(excess);
 		}
+	}
 
-		self.cursor = self.changes.len();
+	pub fn set_capacity(&mut self, capacity: usize) {
+		self.capacity = capacity.max(1);
+		self.trim();
+	}
+
+	/// Collects every entry pushed until [`Self::commit`] into a single batch
+	pub fn begin(&mut self, name: impl Into<String>) {
+		self.commit();
+		self.transaction = Some((name.into(), vec![]));
+	}
+
+	/// Ends the current transaction, recording it unless nothing changed
+	pub fn commit(&mut self) {
+		let Some((name, mut entries)) = self.transaction.take() else {
+			return;
+		};
+
+		match entries.len() {
+			0 => {},
+			1 => self.push(entries.remove(0)),
+			_ => self.push(EditHistoryEntry::Batch { name, entries }),
+		}
+	}
+
+	pub fn entries(&self) -> &[EditHistoryEntry] {
+		&self.changes
+	}
+
+	/// Number of entries currently applied
+	pub fn cursor(&self) -> usize {
+		self.cursor
 	}
 
 	pub fn can_undo(&self) -> bool {
@@ -70,12 +146,14 @@ impl EditHistory {
 			None
 		} else {
 			self.cursor -= 1;
-			self.changes.get(self.cursor).copied()
+			self.changes.get(self.cursor).cloned()
 		}
 	}
 
-	pub fn peek_undo(&self) -> Option<EditHistoryEntry> {
-		self.changes.get(self.cursor.saturating_sub(1)).copied()
+	pub fn peek_undo(&self) -> Option<&EditHistoryEntry> {
+		self.cursor
+			.checked_sub(1)
+			.and_then(|cursor| self.changes.get(cursor))
 	}
 
 	pub fn can_redo(&mut self) -> bool {
@@ -87,16 +165,76 @@ impl EditHistory {
 			None
 		} else {
 			self.cursor += 1;
-			self.changes.get(self.cursor - 1).copied()
+			self.changes.get(self.cursor - 1).cloned()
 		}
 	}
 
-	pub fn peek_redo(&self) -> Option<EditHistoryEntry> {
-		self.changes.get(self.cursor).copied()
+	pub fn peek_redo(&self) -> Option<&EditHistoryEntry> {
+		self.changes.get(self.cursor)
 	}
 }
 
 impl SpectralApp {
+	/// Undoes or redoes entries until exactly `cursor` of them are applied
+	pub fn jump_to_history(&mut self, cursor: usize) {
+		while self.history.cursor() > cursor {
+			let Some(entry) = self.history.undo() else {
+				break;
+			};
+			self.undo(entry);
+		}
+
+		while self.history.cursor() < cursor {
+			let Some(entry) = self.history.redo() else {
+				break;
+			};
+			self.redo(entry);
+		}
+	}
+
+	pub fn draw_history_window(&mut self, ctx: &egui::Context) {
+		let mut open = self.history_open;
+		let mut jump = None;
+
+		egui::Window::new("History")
+			.open(&mut open)
+			.default_width(260.)
+			.collapsible(false)
+			.show(ctx, |ui| {
+				ui.label(format!(
+					"{} of {} entries applied",
+					self.history.cursor(),
+					self.history.entries().len()
+				));
+
+				ui.separator();
+
+				egui::ScrollArea::vertical().show(ui, |ui| {
+					let cursor = self.history.cursor();
+
+					if ui.selectable_label(cursor == 0, "Initial state").clicked() {
+						jump = Some(0);
+					}
+
+					for (i, entry) in self.history.entries().iter().enumerate() {
+						let text = egui::RichText::new(entry.to_string());
+						// Entries past the cursor have been undone
+						let text = if i < cursor { text } else { text.weak() };
+
+						if ui.selectable_label(cursor == i + 1, text).clicked() {
+							jump = Some(i + 1);
+						}
+					}
+				});
+			});
+
+		if let Some(cursor) = jump {
+			self.jump_to_history(cursor);
+		}
+
+		self.history_open = open;
+	}
+
 	pub fn undo(&mut self, entry: EditHistoryEntry) {
 		match entry {
 			EditHistoryEntry::CreateTimingPoint(created_tp) => {
@@ -120,6 +258,12 @@ impl SpectralApp {
 					*tp = before;
 				}
 			},
+			EditHistoryEntry::Batch { entries, .. } => {
+				for entry in entries.into_iter().rev() {
+					self.undo(entry);
+				}
+				self.sort_timing_points();
+			},
 		}
 	}
 
@@ -146,6 +290,12 @@ impl SpectralApp {
 					*tp = after;
 				}
 			},
+			EditHistoryEntry::Batch { entries, .. } => {
+				for entry in entries {
+					self.redo(entry);
+				}
+				self.sort_timing_points();
+			},
 		}
 	}
 }
@@ -3,9 +3,14 @@ use egui::{Color32, FontId, Pos2, Rect, Sense, TextFormat, Vec2};
 
 use crate::app::SpectralApp;
 use crate::app::history::EditHistoryEntry;
+use crate::audio::ChannelSource;
 use crate::colors::COLOR_TEXT_HIGHLIGHT;
-use crate::export::{ExportFormat, export_timing_points};
-use crate::spectrogram::colors::Colormap;
+use crate::export::ExportFormat;
+use crate::onset::OnsetBand;
+use crate::spectrogram::hpss::SpectrogramView;
+use crate::spectrogram::stats::DbRangeMode;
+use crate::timing::{ResnapMode, SwingUnit, TempoRamp, grouping_options, grouping_to_text};
+use crate::waveform::WaveformLane;
 use crate::widgets::time::TimeInput;
 
 impl SpectralApp {
@@ -16,6 +21,10 @@ impl SpectralApp {
 					self.request_open_audio();
 				}
 
+				if ui.button("Settings").clicked() {
+					self.settings_open = !self.settings_open;
+				}
+
 				ui.separator();
 
 				if ui
@@ -29,6 +38,28 @@ impl SpectralApp {
 					self.audio_player.play_pause();
 				}
 
+				if ui
+					.add_enabled(
+						!self.timing_points.read().unwrap().is_empty(),
+						egui::Button::new("Loop measure"),
+					)
+					.on_hover_text("Loop the measure under the playhead (L)")
+					.clicked()
+				{
+					self.toggle_measure_loop();
+				}
+
+				if ui
+					.add_enabled(
+						self.audio_player.get_loop().is_some(),
+						egui::Button::new("Clear loop"),
+					)
+					.on_hover_text("Drag on the ruler to set a loop, click it to clear")
+					.clicked()
+				{
+					self.audio_player.set_loop(None);
+				}
+
 				ui.separator();
 
 				if ui
@@ -59,6 +90,18 @@ impl SpectralApp {
 					self.redo(entry);
 				}
 
+				if ui.button("History").clicked() {
+					self.history_open = !self.history_open;
+				}
+
+				if ui
+					.button("Problems")
+					.on_hover_text("Check the timing points before exporting")
+					.clicked()
+				{
+					self.lint_open = !self.lint_open;
+				}
+
 				ui.separator();
 
 				ui.label("Volume:");
@@ -97,17 +140,24 @@ impl SpectralApp {
 
 				ui.label(format!("{:.0}%", volume * 100.));
 
+				let mut subdivide = self.audio_player.get_metronome_subdivide();
+				if ui
+					.checkbox(&mut subdivide, "Subdivide")
+					.on_hover_text("Also click 8ths, or 16ths in sections swinging 16ths")
+					.changed()
+				{
+					self.audio_player.set_metronome_subdivide(subdivide);
+					self.settings
+						.write(move |s| s.metronome_subdivide = subdivide);
+				}
+
 				ui.separator();
 
 				ui.menu_button("Export", |ui| {
 					ui.set_min_width(200.);
 
 					if ui.button(format!("{}", ExportFormat::Csv)).clicked() {
-						export_timing_points(
-							self.timing_points.read().unwrap().clone(),
-							ExportFormat::Csv,
-							self.event_tx.clone(),
-						);
+						self.request_export(ExportFormat::Csv);
 					}
 
 					ui.separator();
@@ -116,14 +166,23 @@ impl SpectralApp {
 
 					for &fmt in ExportFormat::game_formats() {
 						if ui.button(format!("{}", fmt)).clicked() {
-							export_timing_points(
-								self.timing_points.read().unwrap().clone(),
-								fmt,
-								self.event_tx.clone(),
-							);
+							self.request_export(fmt);
 							ui.close();
 						}
 					}
+
+					ui.separator();
+
+					if ui
+						.add_enabled(
+							self.audio_data.is_some(),
+							egui::Button::new("Spectrogram image (.png)"),
+						)
+						.clicked()
+					{
+						self.open_image_export();
+						ui.close();
+					}
 				});
 			});
 		});
@@ -156,24 +215,83 @@ impl SpectralApp {
 					}
 				});
 
+				ui.checkbox(&mut self.anchor_mode, "Anchor mode")
+					.on_hover_text(
+						"Drag a beat on the timeline to its correct spot, \
+					 rescaling the BPM while the offset stays pinned",
+					);
+
+				ui.horizontal(|ui| {
+					ui.label("Keep following points on beat").on_hover_text(
+						"When a BPM or offset changes, move the next timing point to the \
+							 nearest beat or measure, shifting the ones after it along",
+					);
+
+					let mut resnap_mode = self.resnap_mode;
+					egui::ComboBox::from_id_salt("resnap_mode")
+						.selected_text(format!("{}", resnap_mode))
+						.show_ui(ui, |ui| {
+							for &mode in ResnapMode::all() {
+								ui.selectable_value(&mut resnap_mode, mode, format!("{}", mode));
+							}
+						});
+
+					if self.resnap_mode != resnap_mode {
+						self.resnap_mode = resnap_mode;
+						self.settings.write(move |s| s.resnap_mode = resnap_mode);
+					}
+				});
+
+				if ui
+					.button("Section report")
+					.on_hover_text("List sections that end on a fractional beat")
+					.clicked()
+				{
+					self.section_report_open = !self.section_report_open;
+				}
+
+				ui.separator();
+
+				self.draw_beat_marker_tools(ui);
+
+				ui.separator();
+
+				self.draw_selection_tools(ui);
+
 				ui.separator();
 
 				egui::ScrollArea::vertical().show(ui, |ui| {
 					let mut timing_point_delete = None;
 					let mut resort_timing_points = false;
+					let mut clicked_timing_point = None;
+					// Points edited this frame, as they were before the edit
+					let mut edited = vec![];
 
 					for (i, timing_point) in
 						self.timing_points.write().unwrap().iter_mut().enumerate()
 					{
-						// TODO: selection?
+						let selected = self.selected_timing_points.contains(&timing_point.id());
 						let frame = egui::Frame::new()
-							.fill(Color32::TRANSPARENT)
+							.fill(if selected {
+								ui.visuals().selection.bg_fill.gamma_multiply(0.3)
+							} else {
+								Color32::TRANSPARENT
+							})
 							.inner_margin(4.);
 
 						frame.show(ui, |ui| {
 							ui.vertical(|ui| {
 								ui.horizontal(|ui| {
-									ui.label(format!("#{}", i + 1));
+									if ui
+										.selectable_label(selected, format!("#{}", i + 1))
+										.on_hover_text(
+											"Shift-click to select a range, Ctrl-click to toggle",
+										)
+										.clicked()
+									{
+										clicked_timing_point =
+											Some((timing_point.id(), ui.input(|i| i.modifiers)));
+									}
 
 									if ui.small_button("🗑").clicked() {
 										timing_point_delete = Some(i);
@@ -197,12 +315,7 @@ impl SpectralApp {
 									{
 										resort_timing_points = true;
 										if before != *timing_point {
-											self.history.push(
-												EditHistoryEntry::ModifyTimingPoint {
-													before,
-													after: *timing_point,
-												},
-											);
+											edited.push(before);
 										}
 										self.edited_timing_point = None;
 									}
@@ -213,6 +326,7 @@ impl SpectralApp {
 										.clicked() && timing_point.offset
 										> timing_point.ms_per_beat()
 									{
+										edited.push(*timing_point);
 										timing_point.offset -= timing_point.ms_per_beat();
 									}
 
@@ -226,6 +340,7 @@ impl SpectralApp {
 										.map(|d| d.duration)
 										.unwrap_or_default()
 									{
+										edited.push(*timing_point);
 										timing_point.offset += timing_point.ms_per_beat();
 									}
 								});
@@ -241,6 +356,21 @@ impl SpectralApp {
 											.suffix("BPM"),
 									);
 
+									for (label, hint, factor) in [
+										("÷2", "Halve the BPM", 0.5),
+										("×2", "Double the BPM", 2.),
+										("×1.5", "Multiply the BPM by 1.5", 1.5),
+										("×⅔", "Multiply the BPM by 2/3", 2. / 3.),
+									] {
+										if ui.small_button(label).on_hover_text(hint).clicked() {
+											let before = *timing_point;
+											let bpm =
+												(timing_point.bpm * factor * 100.).round() / 100.;
+											timing_point.bpm = bpm.clamp(1., 999.);
+											edited.push(before);
+										}
+									}
+
 									if response.changed() {
 										if self.edited_timing_point.is_none() {
 											self.edited_timing_point = Some(*timing_point);
@@ -252,12 +382,7 @@ impl SpectralApp {
 										&& (response.drag_stopped() || response.lost_focus())
 									{
 										if before != *timing_point {
-											self.history.push(
-												EditHistoryEntry::ModifyTimingPoint {
-													before,
-													after: *timing_point,
-												},
-											);
+											edited.push(before);
 										}
 										self.edited_timing_point = None;
 									}
@@ -270,26 +395,129 @@ impl SpectralApp {
 									let n_response =
 										ui.add(egui::DragValue::new(&mut n).range(1..=16));
 									ui.label("/");
-									let _m_response =
-										ui.add(egui::DragValue::new(&mut m).range(1..=16));
+									egui::ComboBox::from_id_salt((
+										"denominator",
+										timing_point.id(),
+									))
+									.width(40.)
+									.selected_text(m.to_string())
+									.show_ui(ui, |ui| {
+										for d in [1, 2, 4, 8, 16] {
+											ui.selectable_value(&mut m, d, d.to_string());
+										}
+									});
 
 									if n_response.changed() {
 										if self.edited_timing_point.is_none() {
 											self.edited_timing_point = Some(*timing_point);
 										}
 										timing_point.signature = (n, m);
+										timing_point.grouping = 0;
+									}
+
+									if m != timing_point.signature.1 {
+										edited.push(*timing_point);
+										timing_point.signature = (n, m);
+										timing_point.grouping = 0;
 									}
 
 									if let Some(before) = self.edited_timing_point
 										&& (n_response.drag_stopped() || n_response.lost_focus())
 									{
 										if before != *timing_point {
-											self.history.push(
-												EditHistoryEntry::ModifyTimingPoint {
-													before,
-													after: *timing_point,
-												},
-											);
+											edited.push(before);
+										}
+										self.edited_timing_point = None;
+									}
+								});
+
+								if timing_point.signature.0 > 3 {
+									ui.horizontal(|ui| {
+										ui.label("Grouping:").on_hover_text(
+											"How the beats of a measure are accented",
+										);
+
+										let beats = timing_point.signature.0;
+										let mut grouping = timing_point.group_starts();
+										egui::ComboBox::from_id_salt((
+											"grouping",
+											timing_point.id(),
+										))
+										.selected_text(grouping_to_text(grouping, beats))
+										.show_ui(ui, |ui| {
+											for option in grouping_options(beats) {
+												ui.selectable_value(
+													&mut grouping,
+													option,
+													grouping_to_text(option, beats),
+												);
+											}
+										});
+
+										if grouping != timing_point.group_starts() {
+											edited.push(*timing_point);
+											timing_point.grouping = grouping;
+										}
+									});
+								}
+
+								ui.horizontal(|ui| {
+									ui.label("Swing:");
+
+									let mut swing = timing_point.swing * 100.;
+									let response = ui.add(
+										egui::DragValue::new(&mut swing)
+											.speed(0.5)
+											.range(50.0..=75.0)
+											.suffix("%"),
+									);
+
+									let mut unit = timing_point.swing_unit;
+									egui::ComboBox::from_id_salt(("swing_unit", timing_point.id()))
+										.width(60.)
+										.selected_text(unit.to_string())
+										.show_ui(ui, |ui| {
+											for &u in SwingUnit::all() {
+												ui.selectable_value(&mut unit, u, u.to_string());
+											}
+										});
+
+									if unit != timing_point.swing_unit {
+										edited.push(*timing_point);
+										timing_point.swing_unit = unit;
+									}
+
+									ui.label("Ramp:").on_hover_text(
+										"Move the tempo towards the next point's BPM",
+									);
+
+									let mut ramp = timing_point.ramp;
+									egui::ComboBox::from_id_salt(("ramp", timing_point.id()))
+										.width(90.)
+										.selected_text(ramp.to_string())
+										.show_ui(ui, |ui| {
+											for &r in TempoRamp::all() {
+												ui.selectable_value(&mut ramp, r, r.to_string());
+											}
+										});
+
+									if ramp != timing_point.ramp {
+										edited.push(*timing_point);
+										timing_point.ramp = ramp;
+									}
+
+									if response.changed() {
+										if self.edited_timing_point.is_none() {
+											self.edited_timing_point = Some(*timing_point);
+										}
+										timing_point.swing = swing / 100.;
+									}
+
+									if let Some(before) = self.edited_timing_point
+										&& (response.drag_stopped() || response.lost_focus())
+									{
+										if before != *timing_point {
+											edited.push(before);
 										}
 										self.edited_timing_point = None;
 									}
@@ -310,6 +538,14 @@ impl SpectralApp {
 					if resort_timing_points {
 						self.sort_timing_points();
 					}
+
+					for before in edited {
+						self.finish_timing_point_edit(before);
+					}
+
+					if let Some((id, modifiers)) = clicked_timing_point {
+						self.click_timing_point(id, modifiers);
+					}
 				});
 			});
 	}
@@ -327,14 +563,10 @@ impl SpectralApp {
 				Pos2::new(available.left() + freq_axis_width, available.top()),
 				Vec2::new(available.width() - freq_axis_width, ruler_height),
 			);
+			let ruler_response = ui.allocate_rect(ruler_rect, Sense::click_and_drag());
+			self.handle_ruler_input(ui, ruler_rect, &ruler_response);
 			self.draw_ruler(ui, ruler_rect);
 
-			let freq_rect = Rect::from_min_size(
-				Pos2::new(available.left(), available.top() + ruler_height),
-				Vec2::new(freq_axis_width, timeline_height - ruler_height),
-			);
-			self.draw_frequency_axis(ui, freq_rect);
-
 			let timeline_rect = Rect::from_min_max(
 				Pos2::new(
 					available.left() + freq_axis_width,
@@ -343,11 +575,20 @@ impl SpectralApp {
 				Pos2::new(available.max.x, available.top() + timeline_height),
 			);
 
+			let (spectrogram_rect, _) = self.timeline_lanes(timeline_rect);
+			let freq_rect = Rect::from_min_size(
+				Pos2::new(available.left(), spectrogram_rect.top()),
+				Vec2::new(freq_axis_width, spectrogram_rect.height()),
+			);
+			self.draw_frequency_axis(ui, freq_rect);
+
 			let scrollbar_rect = Rect::from_min_size(
 				Pos2::new(available.left() + freq_axis_width, timeline_rect.bottom()),
 				Vec2::new(timeline_rect.width(), scrollbar_height),
 			);
 
+			self.timeline_width = timeline_rect.width();
+
 			let timeline_response = ui.allocate_rect(timeline_rect, Sense::click_and_drag());
 			self.handle_timeline_input(ui, timeline_rect, &timeline_response);
 
@@ -370,9 +611,73 @@ impl SpectralApp {
 
 				ui.separator();
 
+				ui.label("View");
+
+				let mut view = self.spectrogram_view;
+				egui::ComboBox::from_id_salt("spectrogram_view")
+					.selected_text(format!("{}", view))
+					.show_ui(ui, |ui| {
+						for &v in SpectrogramView::all() {
+							ui.selectable_value(&mut view, v, format!("{}", v));
+						}
+					});
+
+				if self.spectrogram_view != view {
+					self.spectrogram_view = view;
+					self.visible_db_keys.clear();
+					self.settings.write(move |s| s.spectrogram_view = view);
+				}
+
+				ui.separator();
+
+				ui.label("Channels");
+
+				let mut channel_source = self.channel_source;
+				egui::ComboBox::from_id_salt("channel_source")
+					.selected_text(format!("{}", channel_source))
+					.show_ui(ui, |ui| {
+						for &cs in ChannelSource::all() {
+							ui.selectable_value(&mut channel_source, cs, format!("{}", cs));
+						}
+					});
+
+				if self.channel_source != channel_source {
+					let analysis_changed =
+						self.channel_source.analysis_channel() != channel_source.analysis_channel();
+
+					self.channel_source = channel_source;
+					self.settings
+						.write(move |s| s.channel_source = channel_source);
+
+					if analysis_changed {
+						self.compute_onsets();
+						self.compute_spectrum_stats();
+					}
+				}
+
+				ui.separator();
+
 				ui.label("dB range");
 
-				ui.add(
+				let mut mode = self.db_range_mode;
+				egui::ComboBox::from_id_salt("db_range_mode")
+					.selected_text(format!("{}", mode))
+					.show_ui(ui, |ui| {
+						for &m in DbRangeMode::all() {
+							ui.selectable_value(&mut mode, m, format!("{}", m));
+						}
+					});
+
+				if self.db_range_mode != mode {
+					self.db_range_mode = mode;
+					self.visible_db_keys.clear();
+					self.settings.write(move |s| s.db_range_mode = mode);
+				}
+
+				let manual = self.db_range_mode == DbRangeMode::Manual;
+
+				ui.add_enabled(
+					manual,
 					egui_double_slider::DoubleSlider::new(
 						&mut self.min_db,
 						&mut self.max_db,
@@ -382,46 +687,175 @@ impl SpectralApp {
 					.separation_distance(5.),
 				);
 
+				let (min_db, max_db) = self.db_range();
 				let db_label = ui.add(
-					egui::Label::new(format!("{:.1}..{:.1}", self.min_db, self.max_db))
+					egui::Label::new(format!("{:.1}..{:.1}", min_db, max_db))
 						.sense(egui::Sense::click())
 						.selectable(false),
 				);
 
-				if db_label.hovered() {
+				if manual && db_label.hovered() {
 					ui.output_mut(|o| o.cursor_icon = egui::CursorIcon::PointingHand);
 				}
 
-				if db_label.double_clicked() {
+				if manual && db_label.double_clicked() {
 					self.min_db = -80.;
 					self.max_db = 0.;
 				}
 
-				ui.separator();
+				let mut whitening = self.whitening;
+				if ui
+					.checkbox(&mut whitening, "Whiten")
+					.on_hover_text(
+						"Flatten the average spectrum so quiet high frequencies stand out",
+					)
+					.changed()
+				{
+					self.whitening = whitening;
+					self.visible_db_keys.clear();
+					self.settings.write(move |s| s.whitening = whitening);
+				}
 
-				let mut colormap = self.spectrogram_colormap;
+				ui.separator();
 
 				ui.label("Colormap");
 
+				let mut selected = None;
 				egui::ComboBox::from_label("")
-					.selected_text(format!("{:?}", colormap))
+					.selected_text(self.spectrogram_colormap.name())
 					.show_ui(ui, |ui| {
-						for cm in [
-							Colormap::Roseus,
-							Colormap::Arcus,
-							Colormap::Lavendula,
-							Colormap::Magma,
-						] {
-							ui.selectable_value(&mut colormap, cm, format!("{:?}", cm));
+						let current = self.spectrogram_colormap.name();
+
+						for cm in self.colormaps.builtin() {
+							if ui
+								.selectable_label(current == cm.name(), cm.name())
+								.clicked()
+							{
+								selected = Some(cm.clone());
+							}
+						}
+
+						if !self.colormaps.custom().is_empty() {
+							ui.separator();
+						}
+
+						for cm in self.colormaps.custom() {
+							if ui
+								.selectable_label(current == cm.name(), cm.name())
+								.clicked()
+							{
+								selected = Some(cm.clone());
+							}
 						}
 					});
 
-				if self.spectrogram_colormap != colormap {
+				let mut reverse = self.colormap_reverse;
+				ui.checkbox(&mut reverse, "Reverse");
+
+				let mut gamma = self.colormap_gamma;
+				ui.label("Gamma");
+				let gamma_response = ui.add(
+					egui::DragValue::new(&mut gamma)
+						.range(0.2..=5.)
+						.speed(0.01)
+						.fixed_decimals(2),
+				);
+
+				if gamma_response.double_clicked() {
+					gamma = 1.;
+				}
+
+				if let Some(colormap) = selected {
+					let name = colormap.name().to_string();
 					self.spectrogram_colormap = colormap;
-					self.settings.write(move |s| s.colormap = colormap);
+					self.settings.write(move |s| s.colormap = name);
+					self.update_palette();
+				}
 
-					self.cached_spectrogram = None;
-					ctx.request_repaint();
+				if reverse != self.colormap_reverse || gamma != self.colormap_gamma {
+					self.colormap_reverse = reverse;
+					self.colormap_gamma = gamma;
+					self.settings.write(move |s| {
+						s.colormap_reverse = reverse;
+						s.colormap_gamma = gamma;
+					});
+					self.update_palette();
+				}
+			});
+
+			ui.horizontal(|ui| {
+				ui.label("Waveform");
+
+				let mut lane = self.waveform_lane;
+				egui::ComboBox::from_id_salt("waveform_lane")
+					.selected_text(format!("{:?}", lane))
+					.show_ui(ui, |ui| {
+						for l in [
+							WaveformLane::Off,
+							WaveformLane::Below,
+							WaveformLane::Overlay,
+						] {
+							ui.selectable_value(&mut lane, l, format!("{:?}", l));
+						}
+					});
+
+				ui.add_enabled_ui(lane != WaveformLane::Off, |ui| {
+					let mut rms = self.waveform_rms;
+					if ui.checkbox(&mut rms, "RMS").changed() {
+						self.waveform_rms = rms;
+						self.settings.write(move |s| s.waveform_rms = rms);
+					}
+				});
+
+				if self.waveform_lane != lane {
+					self.waveform_lane = lane;
+					self.settings.write(move |s| s.waveform_lane = lane);
+				}
+
+				ui.separator();
+
+				let mut show_onsets = self.show_onsets;
+				if ui.checkbox(&mut show_onsets, "Onsets").changed() {
+					self.show_onsets = show_onsets;
+					self.settings.write(move |s| s.show_onsets = show_onsets);
+				}
+
+				let mut band = self.onset_band;
+				egui::ComboBox::from_id_salt("onset_band")
+					.selected_text(format!("{:?}", band))
+					.show_ui(ui, |ui| {
+						for &b in OnsetBand::all() {
+							ui.selectable_value(&mut band, b, format!("{:?}", b));
+						}
+					});
+
+				if self.onset_band != band {
+					self.onset_band = band;
+					self.settings.write(move |s| s.onset_band = band);
+					self.compute_onsets();
+				}
+
+				let mut percussive = self.onsets_percussive;
+				if ui
+					.checkbox(&mut percussive, "Percussive")
+					.on_hover_text("Detect onsets on the percussive part of the spectrum only")
+					.changed()
+				{
+					self.onsets_percussive = percussive;
+					self.settings
+						.write(move |s| s.onsets_percussive = percussive);
+					self.compute_onsets();
+				}
+
+				let mut snap_to_onsets = self.snap_to_onsets;
+				if ui
+					.checkbox(&mut snap_to_onsets, "Snap to onsets")
+					.on_hover_text("Shift also locks the cursor onto onset peaks")
+					.changed()
+				{
+					self.snap_to_onsets = snap_to_onsets;
+					self.settings
+						.write(move |s| s.snap_to_onsets = snap_to_onsets);
 				}
 			});
 
@@ -468,7 +902,7 @@ impl SpectralApp {
 
 				("Hold "),
 				("Shift", true),
-				(" to lock cursor onto visible ticks\n"),
+				(" to lock cursor onto visible ticks and onset peaks\n"),
 
 				("Right Click", true),
 				(" to seek"),
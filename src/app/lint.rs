@@ -0,0 +1,159 @@
+use crate::app::SpectralApp;
+use crate::export::{ExportFormat, export_timing_points};
+use crate::lint::{Lint, Severity, has_errors, lint_timing_points};
+use crate::util::format_time;
+
+impl SpectralApp {
+	fn lint(&self, fmt: ExportFormat) -> Vec<Lint> {
+		lint_timing_points(
+			&self.timing_points.read().unwrap(),
+			self.audio_data.as_ref().map(|data| data.duration),
+			fmt,
+		)
+	}
+
+	/// Scrolls the timeline to a problem and selects the timing point it is about
+	fn jump_to_lint(&mut self, lint: &Lint) {
+		if let Some(data) = &self.audio_data {
+			self.timeline
+				.scroll_to(lint.ms, data.duration, self.timeline_width);
+		}
+
+		if let Some(tp) = self.timing_points.read().unwrap().get(lint.index) {
+			self.selected_timing_points.clear();
+			self.selected_timing_points.insert(tp.id());
+			self.selection_anchor = Some(tp.id());
+		}
+	}
+
+	/// Exports the timing points, asking for confirmation first if the lint pass
+	/// finds errors for that format
+	pub fn request_export(&mut self, fmt: ExportFormat) {
+		if has_errors(&self.lint(fmt)) {
+			self.pending_export = Some(fmt);
+		} else {
+			self.export(fmt);
+		}
+	}
+
+	fn export(&self, fmt: ExportFormat) {
+		export_timing_points(
+			self.timing_points.read().unwrap().clone(),
+			fmt,
+			self.settings.read(|s| s.ramp_step_beats),
+			self.event_tx.clone(),
+		);
+	}
+
+	pub fn draw_export_warning(&mut self, ctx: &egui::Context) {
+		let Some(fmt) = self.pending_export else {
+			return;
+		};
+
+		let errors = self
+			.lint(fmt)
+			.iter()
+			.filter(|lint| lint.severity == Severity::Error)
+			.count();
+
+		let mut close = false;
+
+		let response = egui::Modal::new(egui::Id::new("export_warning")).show(ctx, |ui| {
+			ui.heading("Export with errors?");
+			ui.label(format!(
+				"The timing points have {} errors for {}.",
+				errors, fmt
+			));
+
+			ui.horizontal(|ui| {
+				if ui.button("Export anyway").clicked() {
+					self.export(fmt);
+					close = true;
+				}
+
+				if ui.button("Show problems").clicked() {
+					self.lint_format = fmt;
+					self.lint_open = true;
+					close = true;
+				}
+
+				if ui.button("Cancel").clicked() {
+					close = true;
+				}
+			});
+		});
+
+		if close || response.should_close() {
+			self.pending_export = None;
+		}
+	}
+
+	pub fn draw_lint_window(&mut self, ctx: &egui::Context) {
+		if !self.lint_open {
+			return;
+		}
+
+		let mut open = true;
+		let mut jump = None;
+		let mut fmt = self.lint_format;
+		let lints = self.lint(fmt);
+
+		egui::Window::new("Problems")
+			.open(&mut open)
+			.default_width(320.)
+			.collapsible(false)
+			.show(ctx, |ui| {
+				ui.horizontal(|ui| {
+					ui.label("Target format");
+
+					egui::ComboBox::from_id_salt("lint_format")
+						.selected_text(format!("{}", fmt))
+						.show_ui(ui, |ui| {
+							for &f in ExportFormat::all() {
+								ui.selectable_value(&mut fmt, f, format!("{}", f));
+							}
+						});
+				});
+
+				ui.separator();
+
+				if lints.is_empty() {
+					ui.label("No problems found");
+					return;
+				}
+
+				egui::ScrollArea::vertical().show(ui, |ui| {
+					for (i, lint) in lints.iter().enumerate() {
+						let (icon, color) = match lint.severity {
+							Severity::Error => ("⛔", ui.visuals().error_fg_color),
+							Severity::Warning => ("⚠", ui.visuals().warn_fg_color),
+						};
+
+						let text = egui::RichText::new(format!(
+							"{} #{} {}  {}",
+							icon,
+							lint.index + 1,
+							format_time(lint.ms),
+							lint.message
+						))
+						.color(color);
+
+						if ui
+							.selectable_label(false, text)
+							.on_hover_text("Show on the timeline")
+							.clicked()
+						{
+							jump = Some(i);
+						}
+					}
+				});
+			});
+
+		if let Some(i) = jump {
+			self.jump_to_lint(&lints[i]);
+		}
+
+		self.lint_format = fmt;
+		self.lint_open = open;
+	}
+}
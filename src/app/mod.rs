@@ -1,26 +1,47 @@
+use std::collections::HashSet;
 use std::env::args;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, RwLock};
 use std::thread::{self, JoinHandle};
 
 use egui::{Rect, Ui};
 
+use crate::app::beat_markers::BeatMarkers;
+use crate::app::calibration::LatencyCalibration;
 use crate::app::history::{EditHistory, EditHistoryEntry};
 use crate::app::modal::ResultModalData;
-use crate::audio::{AudioData, AudioPlayer};
+use crate::app::timing::TimingPointDrag;
+use crate::audio::{AudioData, AudioPlayer, ChannelSource};
+use crate::cache::DiskCache;
 use crate::events::SpectralEvent;
+use crate::export::ExportFormat;
 use crate::metronome::{MetronomeState, metronome_thread};
+use crate::onset::{OnsetBand, OnsetEnvelope};
+use crate::render::RenderOptions;
 use crate::settings::SettingsManager;
-use crate::spectrogram::colors::Colormap;
-use crate::spectrogram::{CachedSpectrogram, Spectrogram};
-use crate::timing::TimingPoint;
+use crate::spectrogram::Spectrogram;
+use crate::spectrogram::colormaps::ColormapLibrary;
+use crate::spectrogram::colors::{Colormap, Palette};
+use crate::spectrogram::hpss::SpectrogramView;
+use crate::spectrogram::stats::{DbRangeMode, SpectrumStats};
+use crate::spectrogram::tiles::{TileCache, TileKey};
+use crate::timing::{ResnapMode, TimingPoint};
+use crate::waveform::{Waveform, WaveformLane};
 use crate::widgets::timeline::Timeline;
 
+mod beat_markers;
+mod calibration;
+mod export;
 mod history;
 mod layout;
+mod lint;
 mod modal;
+mod sections;
+mod selection;
+mod settings;
 mod spectrogram;
 mod timing;
 mod ui;
@@ -31,35 +52,87 @@ enum TimingMode {
 }
 
 pub struct SpectralApp {
-	audio_data: Option<AudioData>,
+	audio_data: Option<Arc<AudioData>>,
 	audio_player: AudioPlayer,
 	audio_loading: bool,
+	audio_load_progress: f32,
+	audio_load_cancel: Arc<AtomicBool>,
 	_metronome: JoinHandle<()>,
 
 	history: EditHistory,
+	history_open: bool,
 	settings: Arc<SettingsManager>,
+	settings_open: bool,
+	latency_calibration: Option<LatencyCalibration>,
+	disk_cache: Arc<DiskCache>,
 
 	event_rx: Receiver<SpectralEvent>,
 	event_tx: Sender<SpectralEvent>,
 
 	spectrogram: Spectrogram,
+	colormaps: ColormapLibrary,
 	spectrogram_colormap: Colormap,
-	cached_spectrogram: Option<CachedSpectrogram>,
+	colormap_reverse: bool,
+	colormap_gamma: f32,
+	spectrogram_palette: Palette,
+	channel_source: ChannelSource,
+	spectrogram_view: SpectrogramView,
+	spectrogram_tiles: TileCache,
 	fft_size: usize,
 	min_db: f32,
 	max_db: f32,
+	db_range_mode: DbRangeMode,
+	whitening: bool,
+	spectrum_stats: Option<SpectrumStats>,
+	visible_db_range: Option<(f32, f32)>,
+	visible_db_keys: Vec<TileKey>,
+	// Column under the pointer, computed when its tile's magnitudes were evicted
+	hover_column: Option<(TileKey, usize, Vec<f32>)>,
+
+	waveform: Option<Waveform>,
+	waveform_lane: WaveformLane,
+	waveform_rms: bool,
+
+	onsets: Option<OnsetEnvelope>,
+	show_onsets: bool,
+	onset_band: OnsetBand,
+	onsets_percussive: bool,
+	snap_to_onsets: bool,
 
 	timeline: Timeline,
+	timeline_width: f32,
 	snap_divisor: i64,
 	hover_ms: Option<f64>,
+	loop_drag_start: Option<f64>,
+	// Start of a box selection on the timeline, in ms and screen y
+	box_select_start: Option<(f64, f32)>,
+	timing_point_drag: Option<TimingPointDrag>,
+	anchor_mode: bool,
+	// Whether playback was running when the playhead drag started
+	scrub_drag: Option<bool>,
+	last_scrub_ms: Option<f64>,
 
 	snap_to_tick: bool,
 	snap_ms: Option<f64>,
 
 	timing_mode: TimingMode,
+	// Set while placing beats by hand for a tempo map
+	beat_markers: Option<BeatMarkers>,
+	selected_timing_points: HashSet<egui::Id>,
+	selection_anchor: Option<egui::Id>,
+	bulk_shift_ms: f64,
+	bulk_bpm_factor: f64,
 
 	timing_points: Arc<RwLock<Vec<TimingPoint>>>,
 	edited_timing_point: Option<TimingPoint>,
+	resnap_mode: ResnapMode,
+	section_report_open: bool,
+
+	image_export: Option<RenderOptions>,
+	// Export waiting for confirmation because the lint pass found errors
+	pending_export: Option<ExportFormat>,
+	lint_open: bool,
+	lint_format: ExportFormat,
 
 	result_data: Option<ResultModalData>,
 }
@@ -69,6 +142,7 @@ impl SpectralApp {
 		let (event_tx, event_rx) = mpsc::channel();
 
 		let settings = Arc::new(SettingsManager::new());
+		let disk_cache = Arc::new(DiskCache::new(&settings));
 
 		let audio_player = AudioPlayer::new(settings.clone()).expect("penis");
 		let timing_points = Arc::new(RwLock::new(vec![
@@ -83,37 +157,91 @@ impl SpectralApp {
 			metronome_thread(state, sink, _tp);
 		});
 
+		let colormaps = ColormapLibrary::load();
+		let spectrogram_colormap = colormaps.get_or_default(&settings.read(|s| s.colormap.clone()));
+		let colormap_reverse = settings.read(|s| s.colormap_reverse);
+		let colormap_gamma = settings.read(|s| s.colormap_gamma);
+		let spectrogram_palette =
+			Palette::new(&spectrogram_colormap, colormap_reverse, colormap_gamma);
+
 		let mut _self = Self {
 			audio_data: None,
 			audio_player,
 			audio_loading: false,
+			audio_load_progress: 0.,
+			audio_load_cancel: Arc::new(AtomicBool::new(false)),
 			_metronome,
 
-			history: EditHistory::default(),
+			history: EditHistory::new(settings.read(|s| s.history_capacity)),
+			history_open: false,
 
 			event_rx,
 			event_tx,
 
 			spectrogram: Spectrogram::new(2048),
-			spectrogram_colormap: settings.read(|s| s.colormap),
-			cached_spectrogram: None,
+			colormaps,
+			spectrogram_colormap,
+			colormap_reverse,
+			colormap_gamma,
+			spectrogram_palette,
+			channel_source: settings.read(|s| s.channel_source),
+			spectrogram_view: settings.read(|s| s.spectrogram_view),
+			spectrogram_tiles: TileCache::new(disk_cache.clone()),
 			fft_size: 2048,
 			min_db: -80.,
 			max_db: 0.,
+			db_range_mode: settings.read(|s| s.db_range_mode),
+			whitening: settings.read(|s| s.whitening),
+			spectrum_stats: None,
+			visible_db_range: None,
+			visible_db_keys: vec![],
+			hover_column: None,
+
+			waveform: None,
+			waveform_lane: settings.read(|s| s.waveform_lane),
+			waveform_rms: settings.read(|s| s.waveform_rms),
+
+			onsets: None,
+			show_onsets: settings.read(|s| s.show_onsets),
+			onset_band: settings.read(|s| s.onset_band),
+			onsets_percussive: settings.read(|s| s.onsets_percussive),
+			snap_to_onsets: settings.read(|s| s.snap_to_onsets),
 
 			timeline: Timeline::new(),
+			timeline_width: 0.,
 			snap_divisor: 4,
 			hover_ms: None,
+			loop_drag_start: None,
+			box_select_start: None,
+			timing_point_drag: None,
+			anchor_mode: false,
+			scrub_drag: None,
+			last_scrub_ms: None,
 
 			snap_to_tick: false,
 			snap_ms: None,
 
 			timing_mode: TimingMode::Idle,
+			beat_markers: None,
+			selected_timing_points: HashSet::new(),
+			selection_anchor: None,
+			bulk_shift_ms: 0.,
+			bulk_bpm_factor: 1.,
 
 			timing_points,
 			edited_timing_point: None,
+			resnap_mode: settings.read(|s| s.resnap_mode),
+			section_report_open: false,
 
 			settings,
+			settings_open: false,
+			latency_calibration: None,
+			disk_cache,
+
+			image_export: None,
+			pending_export: None,
+			lint_open: false,
+			lint_format: ExportFormat::Osu,
 
 			result_data: None,
 		};
@@ -133,15 +261,26 @@ impl SpectralApp {
 			SpectralEvent::OpenAudio { path } => {
 				self.load_audio(path);
 			},
+			SpectralEvent::AudioProgress { progress } => {
+				self.audio_load_progress = progress;
+			},
 			SpectralEvent::LoadAudio { data } => {
+				// Results of cancelled loads are dropped
+				if !self.audio_loading {
+					return;
+				}
 				self.audio_loading = false;
 
 				match data {
 					Ok(data) => {
-						let _ = self.audio_player.load(&data);
+						self.waveform = None;
+						self.load_decoded_audio(&data);
 
 						self.audio_data = Some(data);
-						self.cached_spectrogram = None;
+						self.compute_onsets();
+						self.compute_spectrum_stats();
+						self.spectrogram_tiles.clear();
+						self.hover_column = None;
 						self.timing_points.write().unwrap().clear();
 						self.timeline.reset();
 					},
@@ -150,6 +289,42 @@ impl SpectralApp {
 					},
 				}
 			},
+			SpectralEvent::AudioDecoded { data } => match data {
+				Ok(data) => {
+					if self.audio_data.as_ref().map(|d| d.hash) == Some(data.hash) {
+						self.load_decoded_audio(&data);
+					}
+				},
+				Err(e) => {
+					// Only the cached mono mix was loaded, which can't be played
+					self.unload_audio();
+					self.set_result(format!("Error during audio loading: {:?}", e));
+				},
+			},
+			SpectralEvent::Waveform { waveform } => {
+				if self.audio_data.as_ref().map(|d| d.hash) == Some(waveform.hash) {
+					self.waveform = Some(waveform);
+				}
+			},
+			SpectralEvent::Onsets { onsets } => {
+				if self.audio_data.as_ref().map(|d| d.hash) == Some(onsets.hash)
+					&& onsets.band == self.onset_band
+					&& onsets.percussive == self.onsets_percussive
+					&& onsets.channel == self.channel_source.analysis_channel()
+					&& onsets.fft_size == self.fft_size
+				{
+					self.onsets = Some(onsets);
+				}
+			},
+			SpectralEvent::SpectrumStats { stats } => {
+				if self.audio_data.as_ref().map(|d| d.hash) == Some(stats.hash)
+					&& stats.channel == self.channel_source.analysis_channel()
+					&& stats.fft_size == self.fft_size
+				{
+					self.spectrum_stats = Some(stats);
+					self.visible_db_keys.clear();
+				}
+			},
 			SpectralEvent::Export { error } => {
 				let message = match error {
 					Some(e) => format!("Error during export: {:?}", e),
@@ -168,11 +343,117 @@ impl SpectralApp {
 	fn load_audio(&mut self, path: PathBuf) {
 		self.audio_player.pause();
 		self.audio_loading = true;
+		self.audio_load_progress = 0.;
+
+		// Only the latest load is kept running
+		self.audio_load_cancel.store(true, Ordering::Relaxed);
+		let cancel = Arc::new(AtomicBool::new(false));
+		self.audio_load_cancel = cancel.clone();
+
+		let tx = self.event_tx.clone();
+		let cache = self.disk_cache.clone();
+		thread::spawn(move || {
+			let progress_tx = tx.clone();
+			let cached_tx = tx.clone();
+			let mut last_percent = 0;
+			let mut sent_cached = false;
+			let data = AudioData::load_from_file(
+				path,
+				&cache,
+				|progress| {
+					let percent = (progress * 100.) as u32;
+					if percent != last_percent {
+						last_percent = percent;
+						let _ = progress_tx.send(SpectralEvent::AudioProgress { progress });
+					}
+				},
+				&cancel,
+				|data| {
+					sent_cached = true;
+					let _ = cached_tx.send(SpectralEvent::LoadAudio { data: Ok(data) });
+				},
+			);
+
+			if cancel.load(Ordering::Relaxed) {
+				return;
+			}
+
+			let _ = tx.send(if sent_cached {
+				SpectralEvent::AudioDecoded { data }
+			} else {
+				SpectralEvent::LoadAudio { data }
+			});
+		});
+	}
+
+	fn unload_audio(&mut self) {
+		self.audio_player.pause();
+		self.audio_data = None;
+		self.waveform = None;
+		self.onsets = None;
+		self.spectrum_stats = None;
+		self.visible_db_range = None;
+		self.visible_db_keys.clear();
+		self.spectrogram_tiles.clear();
+		self.hover_column = None;
+	}
+
+	/// Hands the interleaved samples to the player and the waveform, if decoding is
+	/// done. Otherwise the player is only stopped.
+	fn load_decoded_audio(&mut self, data: &AudioData) {
+		let _ = self.audio_player.load(data);
+
+		let Some(samples) = data.samples() else {
+			return;
+		};
+
+		let tx = self.event_tx.clone();
+		let (hash, channels) = (data.hash, data.channels);
+		thread::spawn(move || {
+			let waveform = Waveform::new(hash, samples, channels);
+			let _ = tx.send(SpectralEvent::Waveform { waveform });
+		});
+	}
+
+	fn cancel_audio_loading(&mut self) {
+		self.audio_load_cancel.store(true, Ordering::Relaxed);
+		self.audio_loading = false;
+	}
+
+	fn compute_onsets(&mut self) {
+		self.onsets = None;
+
+		let Some(data) = self.audio_data.clone() else {
+			return;
+		};
 
 		let tx = self.event_tx.clone();
+		let cache = self.disk_cache.clone();
+		let band = self.onset_band;
+		let percussive = self.onsets_percussive;
+		let channel = self.channel_source.analysis_channel();
+		let fft_size = self.fft_size;
 		thread::spawn(move || {
-			let data = AudioData::load_from_file(path);
-			let _ = tx.send(SpectralEvent::LoadAudio { data });
+			let onsets = OnsetEnvelope::compute(&data, channel, fft_size, band, percussive, &cache);
+			let _ = tx.send(SpectralEvent::Onsets { onsets });
+		});
+	}
+
+	fn compute_spectrum_stats(&mut self) {
+		self.spectrum_stats = None;
+		self.visible_db_range = None;
+		self.visible_db_keys.clear();
+
+		let Some(data) = self.audio_data.clone() else {
+			return;
+		};
+
+		let tx = self.event_tx.clone();
+		let channel = self.channel_source.analysis_channel();
+		let fft_size = self.fft_size;
+		thread::spawn(move || {
+			let stats = SpectrumStats::compute(&data, channel, fft_size);
+			let _ = tx.send(SpectralEvent::SpectrumStats { stats });
 		});
 	}
 
@@ -188,6 +469,128 @@ impl SpectralApp {
 		});
 	}
 
+	fn handle_ruler_input(&mut self, ui: &mut Ui, rect: Rect, response: &egui::Response) {
+		if self.audio_loading || self.audio_data.is_none() {
+			return;
+		}
+
+		// Loop edges snap to beat ticks within 10 pixels while snapping is enabled
+		let pointer_ms = |app: &Self, x: f32| {
+			let ms = app.timeline.x_to_ms(x, rect);
+			if !app.snap_to_tick {
+				return ms;
+			}
+
+			let radius = app.timeline.x_to_ms(x + 10., rect) - ms;
+			app.nearest_beat_tick(ms, radius).unwrap_or(ms)
+		};
+
+		if response.drag_started()
+			&& let Some(origin) = ui.input(|i| i.pointer.press_origin())
+		{
+			self.loop_drag_start = Some(pointer_ms(self, origin.x));
+		}
+
+		if let Some(start) = self.loop_drag_start
+			&& let Some(pos) = response.interact_pointer_pos()
+		{
+			let end = pointer_ms(self, pos.x);
+			if (end - start).abs() > 0. {
+				self.audio_player.set_loop(Some((start, end)));
+			}
+		}
+
+		if response.drag_stopped() {
+			self.loop_drag_start = None;
+
+			// Playback only wraps when it reaches the loop end
+			let position = self.audio_player.get_position_ms();
+			if let Some((start, end)) = self.audio_player.get_loop()
+				&& !(start..end).contains(&position)
+			{
+				self.audio_player.seek_to(start);
+			}
+		}
+
+		if response.clicked() {
+			self.audio_player.set_loop(None);
+		}
+	}
+
+	/// Dragging the playhead seeks while playing grains under the pointer, as does
+	/// moving over the timeline with Alt held
+	fn handle_scrubbing(
+		&mut self,
+		ui: &mut Ui,
+		rect: Rect,
+		response: &egui::Response,
+		duration: f64,
+	) {
+		let pointer_ms = response
+			.interact_pointer_pos()
+			.or(ui.input(|i| i.pointer.hover_pos()))
+			.map(|pos| self.timeline.x_to_ms(pos.x, rect).clamp(0., duration));
+
+		if response.drag_started_by(egui::PointerButton::Primary)
+			&& let Some(origin) = ui.input(|i| i.pointer.press_origin())
+		{
+			let playhead_x = self
+				.timeline
+				.ms_to_x(self.audio_player.get_audible_position_ms(), rect);
+
+			if (origin.x - playhead_x).abs() <= 6. {
+				self.scrub_drag = Some(self.audio_player.is_playing());
+				self.audio_player.pause();
+			}
+		}
+
+		let scrubbing = if self.scrub_drag.is_some() {
+			if let Some(ms) = pointer_ms {
+				self.audio_player.seek_to(ms);
+			}
+			true
+		} else {
+			// An Alt-drag from a marker changes its BPM, even on the frame before the
+			// drag is claimed
+			let on_marker = ui
+				.input(|i| i.pointer.primary_down().then(|| i.pointer.press_origin()))
+				.flatten()
+				.is_some_and(|origin| self.timing_point_at(origin.x, rect).is_some());
+
+			response.hovered()
+				&& ui.input(|i| i.modifiers.alt)
+				&& self.timing_point_drag.is_none()
+				&& !on_marker
+		};
+
+		if scrubbing && let Some(ms) = pointer_ms {
+			if self.last_scrub_ms != Some(ms) {
+				let _ = self.audio_player.scrub(ms);
+			}
+			self.last_scrub_ms = Some(ms);
+		} else {
+			self.last_scrub_ms = None;
+		}
+
+		if response.drag_stopped()
+			&& let Some(was_playing) = self.scrub_drag.take()
+			&& was_playing
+		{
+			self.audio_player.play();
+		}
+
+		if !ui.ctx().wants_keyboard_input()
+			&& ui.input(|i| i.key_pressed(egui::Key::P))
+			&& let Some(ms) = if self.snap_to_tick {
+				self.snap_ms
+			} else {
+				self.hover_ms
+			} {
+			let length = self.settings.read(|s| s.preview_length_ms);
+			let _ = self.audio_player.play_preview(ms, length);
+		}
+	}
+
 	fn handle_timeline_input(&mut self, ui: &mut Ui, rect: Rect, response: &egui::Response) {
 		if self.audio_loading {
 			return;
@@ -225,6 +628,27 @@ impl SpectralApp {
 			self.hover_ms = None;
 		}
 
+		self.handle_scrubbing(ui, rect, response, duration);
+		self.handle_timing_point_drag(ui, rect, response);
+
+		if response.drag_started_by(egui::PointerButton::Primary)
+			&& self.scrub_drag.is_none()
+			&& self.timing_point_drag.is_none()
+			&& let Some(origin) = ui.input(|i| i.pointer.press_origin())
+		{
+			self.box_select_start = Some((self.timeline.x_to_ms(origin.x, rect), origin.y));
+		}
+
+		if response.drag_stopped()
+			&& let Some((start, _)) = self.box_select_start.take()
+			&& let Some(pos) = response
+				.interact_pointer_pos()
+				.or(ui.input(|i| i.pointer.latest_pos()))
+		{
+			let add = ui.input(|i| i.modifiers.command);
+			self.select_range(start, self.timeline.x_to_ms(pos.x, rect), add);
+		}
+
 		if response.dragged_by(egui::PointerButton::Middle) {
 			let delta = response.drag_delta();
 			self.timeline
@@ -238,7 +662,13 @@ impl SpectralApp {
 				self.hover_ms
 			};
 
-			if let Some(click_ms) = ms {
+			if let Some(click_ms) = ms
+				&& self.beat_markers.is_some()
+			{
+				if let Some(pos) = response.interact_pointer_pos() {
+					self.click_beat_marker(click_ms, pos.x, rect);
+				}
+			} else if let Some(click_ms) = ms {
 				match self.timing_mode {
 					TimingMode::Idle => {
 						self.timing_mode = TimingMode::SelectedStart { start: click_ms };
@@ -293,14 +723,22 @@ impl eframe::App for SpectralApp {
 			self.handle_event(SpectralEvent::OpenAudio { path });
 		}
 
-		if ctx.input(|i| i.key_pressed(egui::Key::Space)) {
+		if self.latency_calibration.is_none() && ctx.input(|i| i.key_pressed(egui::Key::Space)) {
 			self.audio_player.play_pause();
 		}
 
+		if !ctx.wants_keyboard_input() && ctx.input(|i| i.key_pressed(egui::Key::L)) {
+			self.toggle_measure_loop();
+		}
+
 		if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
 			self.timing_mode = TimingMode::Idle;
+			self.selected_timing_points.clear();
 		}
 
+		self.handle_selection_shortcuts(ctx);
+		self.handle_beat_marker_keys(ctx);
+
 		if ctx.input_mut(|i| {
 			i.consume_shortcut(&egui::KeyboardShortcut::new(
 				egui::Modifiers::CTRL,
@@ -321,7 +759,10 @@ impl eframe::App for SpectralApp {
 			self.redo(entry);
 		}
 
-		if self.audio_player.is_playing() {
+		if self.audio_player.is_playing()
+			|| self.audio_loading
+			|| self.audio_player.get_preview_position_ms().is_some()
+		{
 			ctx.request_repaint();
 		}
 
@@ -331,6 +772,13 @@ impl eframe::App for SpectralApp {
 		self.draw_timing_points_panel(ctx);
 		self.draw_main_contents(ctx);
 
+		self.draw_settings_window(ctx);
+		self.draw_calibration_window(ctx);
+		self.draw_history_window(ctx);
+		self.draw_section_report(ctx);
+		self.draw_lint_window(ctx);
+		self.draw_export_warning(ctx);
+		self.draw_image_export_window(ctx);
 		self.draw_result_modal(ctx);
 	}
 }
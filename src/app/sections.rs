@@ -0,0 +1,156 @@
+use crate::app::SpectralApp;
+use crate::app::history::EditHistoryEntry;
+use crate::lint::FRACTIONAL_TOLERANCE_MS;
+use crate::timing::{ResnapMode, TimingPoint, fractional_sections, resnap_following};
+use crate::util::format_time;
+
+impl SpectralApp {
+	/// Records the edit of a timing point, `before` being its state when the edit
+	/// started. Following points are moved back on beat if enabled.
+	pub fn finish_timing_point_edit(&mut self, before: TimingPoint) {
+		self.sort_timing_points();
+
+		let mut timing_points = self.timing_points.write().unwrap();
+		let Some(index) = timing_points.iter().position(|tp| tp.id() == before.id()) else {
+			return;
+		};
+
+		let after = timing_points[index];
+		if after == before {
+			return;
+		}
+
+		let moved = if after.bpm != before.bpm || after.offset != before.offset {
+			resnap_following(&mut timing_points, index, self.resnap_mode)
+		} else {
+			vec![]
+		};
+		drop(timing_points);
+
+		let entry = EditHistoryEntry::ModifyTimingPoint { before, after };
+		self.history.begin(entry.to_string());
+		self.history.push(entry);
+		self.push_moved(moved);
+		self.history.commit();
+	}
+
+	fn push_moved(&mut self, moved: Vec<(TimingPoint, TimingPoint)>) {
+		for (before, after) in moved {
+			self.history
+				.push(EditHistoryEntry::ModifyTimingPoint { before, after });
+		}
+	}
+
+	/// Snaps the end of section `index` to a whole beat or measure
+	fn fix_section(&mut self, index: usize, mode: ResnapMode) {
+		let moved = resnap_following(&mut self.timing_points.write().unwrap(), index, mode);
+
+		self.history.begin(format!(
+			"Snap end of section #{} to {}",
+			index + 1,
+			mode.to_string().to_lowercase()
+		));
+		self.push_moved(moved);
+		self.history.commit();
+	}
+
+	/// Snaps the end of every section, earliest first so each fix carries over to
+	/// the sections after it
+	fn fix_all_sections(&mut self, mode: ResnapMode) {
+		self.history.begin(format!(
+			"Snap all sections to {}",
+			mode.to_string().to_lowercase()
+		));
+
+		let count = self.timing_points.read().unwrap().len();
+		for index in 0..count {
+			let moved = resnap_following(&mut self.timing_points.write().unwrap(), index, mode);
+			self.push_moved(moved);
+		}
+
+		self.history.commit();
+	}
+
+	pub fn draw_section_report(&mut self, ctx: &egui::Context) {
+		let mut open = self.section_report_open;
+		let mut fix = None;
+		let mut fix_all = None;
+
+		let (sections, timing_points) = {
+			let timing_points = self.timing_points.read().unwrap();
+			(
+				fractional_sections(&timing_points, FRACTIONAL_TOLERANCE_MS),
+				timing_points.clone(),
+			)
+		};
+
+		egui::Window::new("Section report")
+			.open(&mut open)
+			.default_width(320.)
+			.collapsible(false)
+			.show(ctx, |ui| {
+				if sections.is_empty() {
+					ui.label("Every section ends on a whole beat");
+					return;
+				}
+
+				ui.label(format!(
+					"{} sections end on a fractional beat",
+					sections.len()
+				));
+
+				ui.horizontal(|ui| {
+					ui.label("Fix all:");
+					for &mode in &[ResnapMode::Beat, ResnapMode::Measure] {
+						if ui.button(format!("{}", mode)).clicked() {
+							fix_all = Some(mode);
+						}
+					}
+				});
+
+				ui.separator();
+
+				egui::ScrollArea::vertical().show(ui, |ui| {
+					egui::Grid::new("section_report")
+						.striped(true)
+						.show(ui, |ui| {
+							for &(index, beats) in &sections {
+								let tp = &timing_points[index];
+
+								ui.label(format!("#{}", index + 1));
+								ui.label(format_time(tp.offset));
+								ui.label(format!("{:.3} beats", beats));
+
+								if ui
+									.small_button("Beat")
+									.on_hover_text("Move the next point to the nearest beat")
+									.clicked()
+								{
+									fix = Some((index, ResnapMode::Beat));
+								}
+
+								if ui
+									.small_button("Measure")
+									.on_hover_text("Move the next point to the nearest measure")
+									.clicked()
+								{
+									fix = Some((index, ResnapMode::Measure));
+								}
+
+								ui.end_row();
+							}
+						});
+				});
+			});
+
+		if let Some((index, mode)) = fix {
+			self.fix_section(index, mode);
+		}
+
+		if let Some(mode) = fix_all {
+			self.fix_all_sections(mode);
+		}
+
+		self.section_report_open = open;
+	}
+}
@@ -0,0 +1,284 @@
+use egui::Modifiers;
+
+use crate::app::SpectralApp;
+use crate::app::history::EditHistoryEntry;
+use crate::export::{timing_points_from_csv, timing_points_to_csv};
+use crate::timing::TimingPoint;
+
+impl SpectralApp {
+	pub fn is_selected(&self, tp: &TimingPoint) -> bool {
+		self.selected_timing_points.contains(&tp.id())
+	}
+
+	/// Updates the selection for a click on a timing point in the list, Shift
+	/// extends it from the last clicked point and Ctrl toggles a single point
+	pub fn click_timing_point(&mut self, id: egui::Id, modifiers: Modifiers) {
+		if modifiers.shift
+			&& let Some(anchor) = self.selection_anchor
+		{
+			let timing_points = self.timing_points.read().unwrap();
+			let position = |id| timing_points.iter().position(|tp| tp.id() == id);
+
+			if let (Some(from), Some(to)) = (position(anchor), position(id)) {
+				if !modifiers.command {
+					self.selected_timing_points.clear();
+				}
+
+				self.selected_timing_points.extend(
+					timing_points[from.min(to)..=from.max(to)]
+						.iter()
+						.map(|tp| tp.id()),
+				);
+			}
+			return;
+		}
+
+		if modifiers.command {
+			if !self.selected_timing_points.remove(&id) {
+				self.selected_timing_points.insert(id);
+			}
+		} else {
+			self.selected_timing_points.clear();
+			self.selected_timing_points.insert(id);
+		}
+
+		self.selection_anchor = Some(id);
+	}
+
+	/// Selects every timing point between `start` and `end`, adding to the current
+	/// selection if `add` is set
+	pub fn select_range(&mut self, start: f64, end: f64, add: bool) {
+		if !add {
+			self.selected_timing_points.clear();
+		}
+
+		let (start, end) = (start.min(end), start.max(end));
+		self.selected_timing_points.extend(
+			self.timing_points
+				.read()
+				.unwrap()
+				.iter()
+				.filter(|tp| (start..=end).contains(&tp.offset))
+				.map(|tp| tp.id()),
+		);
+	}
+
+	pub fn select_all(&mut self) {
+		self.selected_timing_points = self
+			.timing_points
+			.read()
+			.unwrap()
+			.iter()
+			.map(|tp| tp.id())
+			.collect();
+	}
+
+	pub fn selected(&self) -> Vec<TimingPoint> {
+		self.timing_points
+			.read()
+			.unwrap()
+			.iter()
+			.filter(|tp| self.is_selected(tp))
+			.copied()
+			.collect()
+	}
+
+	/// Applies `f` to every selected timing point, recorded as a single history entry
+	fn modify_selection(&mut self, name: String, f: impl Fn(&mut TimingPoint)) {
+		self.history.begin(name);
+
+		for tp in self.timing_points.write().unwrap().iter_mut() {
+			if !self.selected_timing_points.contains(&tp.id()) {
+				continue;
+			}
+
+			let before = *tp;
+			f(tp);
+
+			if before != *tp {
+				self.history
+					.push(EditHistoryEntry::ModifyTimingPoint { before, after: *tp });
+			}
+		}
+
+		self.history.commit();
+		self.sort_timing_points();
+	}
+
+	pub fn shift_selection(&mut self, delta_ms: f64) {
+		let name = format!(
+			"Shift {} timing points by {} ms",
+			self.selected_timing_points.len(),
+			delta_ms
+		);
+
+		self.modify_selection(name, |tp| tp.offset = (tp.offset + delta_ms).max(0.));
+	}
+
+	pub fn scale_selection_bpm(&mut self, factor: f64) {
+		let name = format!(
+			"Scale BPM of {} timing points by {}",
+			self.selected_timing_points.len(),
+			factor
+		);
+
+		self.modify_selection(name, |tp| {
+			tp.bpm = ((tp.bpm * factor * 100.).round() / 100.).clamp(1., 999.)
+		});
+	}
+
+	pub fn delete_selection(&mut self) {
+		let deleted = self.selected();
+		if deleted.is_empty() {
+			return;
+		}
+
+		self.timing_points
+			.write()
+			.unwrap()
+			.retain(|tp| !self.selected_timing_points.contains(&tp.id()));
+		self.selected_timing_points.clear();
+
+		self.history
+			.begin(format!("Delete {} timing points", deleted.len()));
+		for tp in deleted {
+			self.history.push(EditHistoryEntry::DeleteTimingPoint(tp));
+		}
+		self.history.commit();
+	}
+
+	/// Puts the selected timing points on the clipboard in the CSV export format
+	pub fn copy_selection(&self, ctx: &egui::Context) {
+		let selected = self.selected();
+		if !selected.is_empty() {
+			ctx.copy_text(timing_points_to_csv(&selected));
+		}
+	}
+
+	/// Pastes copied timing points so that the first one lands on the playhead,
+	/// selecting the pasted points
+	pub fn paste_at_playhead(&mut self, contents: &str) {
+		let Ok(pasted) = timing_points_from_csv(contents) else {
+			return;
+		};
+		let Some(first) = pasted.iter().map(|tp| tp.offset).min_by(f64::total_cmp) else {
+			return;
+		};
+
+		let shift = self.audio_player.get_audible_position_ms().round() - first;
+		let pasted: Vec<TimingPoint> = pasted
+			.into_iter()
+			.map(|mut tp| {
+				tp.offset += shift;
+				tp
+			})
+			.collect();
+
+		self.selected_timing_points = pasted.iter().map(|tp| tp.id()).collect();
+		self.timing_points
+			.write()
+			.unwrap()
+			.extend(pasted.iter().copied());
+		self.sort_timing_points();
+
+		self.history
+			.begin(format!("Paste {} timing points", pasted.len()));
+		for tp in pasted {
+			self.history.push(EditHistoryEntry::CreateTimingPoint(tp));
+		}
+		self.history.commit();
+	}
+
+	/// Copy, paste, delete and select all, unless a text field has focus
+	pub fn handle_selection_shortcuts(&mut self, ctx: &egui::Context) {
+		if ctx.wants_keyboard_input() {
+			return;
+		}
+
+		let (copy, paste, delete, select_all) = ctx.input(|i| {
+			(
+				i.events.iter().any(|e| matches!(e, egui::Event::Copy)),
+				i.events.iter().find_map(|e| match e {
+					egui::Event::Paste(contents) => Some(contents.clone()),
+					_ => None,
+				}),
+				i.key_pressed(egui::Key::Delete),
+				i.modifiers.command && i.key_pressed(egui::Key::A),
+			)
+		});
+
+		if copy {
+			self.copy_selection(ctx);
+		}
+
+		if let Some(contents) = paste {
+			self.paste_at_playhead(&contents);
+		}
+
+		if delete {
+			self.delete_selection();
+		}
+
+		if select_all {
+			self.select_all();
+		}
+	}
+
+	pub fn draw_selection_tools(&mut self, ui: &mut egui::Ui) {
+		let count = self.selected_timing_points.len();
+
+		ui.horizontal(|ui| {
+			ui.label(format!("{} selected", count));
+
+			if ui.small_button("All").clicked() {
+				self.select_all();
+			}
+
+			if ui
+				.add_enabled(count > 0, egui::Button::new("None").small())
+				.clicked()
+			{
+				self.selected_timing_points.clear();
+			}
+		});
+
+		ui.add_enabled_ui(count > 0, |ui| {
+			ui.horizontal(|ui| {
+				ui.label("Shift by");
+				ui.add(
+					egui::DragValue::new(&mut self.bulk_shift_ms)
+						.speed(1)
+						.suffix(" ms"),
+				);
+
+				if ui.button("Apply").clicked() {
+					self.shift_selection(self.bulk_shift_ms);
+				}
+			});
+
+			ui.horizontal(|ui| {
+				ui.label("Scale BPM by");
+				ui.add(
+					egui::DragValue::new(&mut self.bulk_bpm_factor)
+						.speed(0.01)
+						.range(0.01..=100.0)
+						.prefix("×"),
+				);
+
+				if ui.button("Apply").clicked() {
+					self.scale_selection_bpm(self.bulk_bpm_factor);
+				}
+			});
+
+			ui.horizontal(|ui| {
+				if ui.button("Delete").clicked() {
+					self.delete_selection();
+				}
+
+				if ui.button("Copy").clicked() {
+					self.copy_selection(ui.ctx());
+				}
+			});
+		});
+	}
+}
@@ -0,0 +1,162 @@
+use crate::app::SpectralApp;
+use crate::spectrogram::colormaps::ColormapLibrary;
+
+impl SpectralApp {
+	pub fn draw_settings_window(&mut self, ctx: &egui::Context) {
+		let mut open = self.settings_open;
+
+		egui::Window::new("Settings")
+			.open(&mut open)
+			.resizable(false)
+			.collapsible(false)
+			.show(ctx, |ui| {
+				ui.heading("Audio");
+
+				ui.horizontal(|ui| {
+					ui.label("Output latency:");
+
+					let mut latency = self.audio_player.get_output_latency();
+					if ui
+						.add(
+							egui::DragValue::new(&mut latency)
+								.range(-200.0..=1000.0)
+								.speed(1)
+								.suffix(" ms"),
+						)
+						.on_hover_text("Shifts the playhead to match what is heard")
+						.changed()
+					{
+						self.audio_player.set_output_latency(latency);
+						self.settings.write(move |s| s.output_latency_ms = latency);
+					}
+
+					if ui.button("Calibrate...").clicked() {
+						self.latency_calibration = Some(Default::default());
+					}
+				});
+
+				ui.horizontal(|ui| {
+					ui.label("Preview length:");
+
+					let mut length = self.settings.read(|s| s.preview_length_ms);
+					if ui
+						.add(
+							egui::DragValue::new(&mut length)
+								.range(50.0..=5000.0)
+								.speed(10)
+								.suffix(" ms"),
+						)
+						.on_hover_text("Audio played from the pointer when pressing P")
+						.changed()
+					{
+						self.settings.write(move |s| s.preview_length_ms = length);
+					}
+				});
+
+				ui.separator();
+
+				ui.heading("History");
+
+				ui.horizontal(|ui| {
+					ui.label("Undo steps:");
+
+					let mut capacity = self.settings.read(|s| s.history_capacity);
+					if ui
+						.add(egui::DragValue::new(&mut capacity).range(10..=10000))
+						.changed()
+					{
+						self.history.set_capacity(capacity);
+						self.settings.write(move |s| s.history_capacity = capacity);
+					}
+				});
+
+				ui.separator();
+
+				ui.heading("Export");
+
+				ui.horizontal(|ui| {
+					ui.label("Tempo ramp steps:");
+
+					let mut step = self.settings.read(|s| s.ramp_step_beats);
+					if ui
+						.add(
+							egui::DragValue::new(&mut step)
+								.range(0.25..=16.0)
+								.speed(0.05)
+								.suffix(" beats"),
+						)
+						.on_hover_text(
+							"Length of the constant sections ramps are split into for formats \
+							 without tempo ramps",
+						)
+						.changed()
+					{
+						self.settings.write(move |s| s.ramp_step_beats = step);
+					}
+				});
+
+				ui.separator();
+
+				ui.heading("Cache");
+
+				ui.horizontal(|ui| {
+					ui.label("Size limit:");
+
+					let mut limit = self.settings.read(|s| s.cache_limit_mb);
+					if ui
+						.add(
+							egui::DragValue::new(&mut limit)
+								.range(64..=65536)
+								.speed(16)
+								.suffix(" MB"),
+						)
+						.changed()
+					{
+						self.disk_cache.set_limit(limit);
+						self.settings.write(move |s| s.cache_limit_mb = limit);
+					}
+				});
+
+				ui.horizontal(|ui| {
+					ui.label(format!(
+						"Using {:.1} MB",
+						self.disk_cache.size() as f64 / 1024. / 1024.
+					));
+
+					if ui.button("Clear cache").clicked() {
+						self.disk_cache.clear();
+					}
+				});
+
+				ui.separator();
+
+				ui.heading("Colormaps");
+
+				ui.label(format!(
+					"Custom colormaps (.csv, .json or .png) are loaded from {}",
+					ColormapLibrary::dir().display()
+				));
+
+				ui.horizontal(|ui| {
+					ui.label(format!(
+						"{} custom colormaps loaded",
+						self.colormaps.custom().len()
+					));
+
+					if ui.button("Reload").clicked() {
+						self.colormaps = ColormapLibrary::load();
+						self.spectrogram_colormap = self
+							.colormaps
+							.get_or_default(&self.settings.read(|s| s.colormap.clone()));
+						self.update_palette();
+					}
+				});
+
+				for error in self.colormaps.errors() {
+					ui.colored_label(ui.visuals().error_fg_color, error);
+				}
+			});
+
+		self.settings_open = open;
+	}
+}
@@ -1,89 +1,250 @@
-use egui::{ColorImage, TextureHandle};
+use egui::{Pos2, Rect, TextureHandle};
 
 use crate::app::SpectralApp;
-use crate::spectrogram::{CachedSpectrogram, Spectrogram};
+use crate::audio::Channel;
+use crate::spectrogram::Spectrogram;
+use crate::spectrogram::colors::Palette;
+use crate::spectrogram::stats::{DbRangeMode, Whitening};
+use crate::spectrogram::tiles::{TileKey, TileStyle};
+use crate::timing::musical_position;
+use crate::util::note_name;
+
+pub struct VisibleTile {
+	pub channel: Channel,
+	pub texture: TextureHandle,
+	pub start: f64,
+	pub end: f64,
+	/// Horizontal uv extent, tiles past the end of the audio are clipped
+	pub u_end: f64,
+}
+
+/// Values under the pointer, shown while hovering the spectrogram
+pub struct HoverReadout {
+	pub ms: f64,
+	/// Measure and beat within it
+	pub position: Option<(i64, f64)>,
+	pub frequency: f32,
+	pub db: f32,
+	/// Nearest note and the offset from it in cents
+	pub note: Option<(String, i32)>,
+}
 
 impl SpectralApp {
-	pub fn generate_spectrogram(
-		&mut self,
-		ctx: &egui::Context,
-		width: usize,
-		height: usize,
-	) -> Option<(TextureHandle, f64, f64)> {
-		let audio = self.audio_data.as_ref()?;
-
-		let (vis_start, vis_end) = self.timeline.visible_range(width as _);
-
-		if let Some(cached) = &self.cached_spectrogram
-			&& cached.is_valid(
-				vis_start,
-				vis_end,
-				self.fft_size,
-				self.min_db,
-				self.max_db,
-				self.timeline.pixels_per_second,
-			) {
-			let (x_from, x_to) = cached.uv(vis_start, vis_end);
-			return Some((cached.texture.clone(), x_from, x_to));
-		}
+	pub fn frequency_to_y(&self, frequency: f32, max_frequency: f32, row: Rect) -> f32 {
+		row.bottom() - (frequency / max_frequency) * row.height()
+	}
 
-		if self.fft_size != self.spectrogram.fft_size {
-			self.spectrogram = Spectrogram::new(self.fft_size);
+	pub fn y_to_frequency(&self, y: f32, max_frequency: f32, row: Rect) -> f32 {
+		(row.bottom() - y) / row.height() * max_frequency
+	}
+
+	pub fn hover_readout(&mut self, pos: Pos2, rect: Rect) -> Option<HoverReadout> {
+		let audio = self.audio_data.clone()?;
+
+		let (channel, row) = self
+			.spectrogram_rows(rect)
+			.into_iter()
+			.find(|(_, row)| row.contains(pos))?;
+
+		let ms = self.timeline.x_to_ms(pos.x, rect);
+		if ms < 0. || ms > audio.duration {
+			return None;
 		}
 
-		let vis_len = vis_end - vis_start;
-		let spec_start = (vis_start - vis_len / 4.).max(0.);
-		let spec_end = (vis_end + vis_len / 4.).min(audio.duration);
+		let max_frequency = audio.sample_rate as f32 / 2.;
+		let frequency = self.y_to_frequency(pos.y, max_frequency, row);
 
-		let spec_width = (spec_end - spec_start) / vis_len * width as f64;
+		let fft_size = self.spectrogram.fft_size;
+		let bins = fft_size / 2;
+		let bin = ((frequency / max_frequency * bins as f32) as usize).min(bins - 1);
 
-		let columns = self.spectrogram.compute_range(
-			audio,
-			spec_start,
-			spec_end,
-			spec_width as _,
-			self.min_db,
-			self.max_db,
+		let (key, column) = TileKey::at(
+			ms,
+			self.timeline.pixels_per_second,
+			fft_size,
+			channel,
+			self.spectrogram_view,
 		);
+		let db = match self.spectrogram_tiles.magnitude(key, column, bin) {
+			Some(db) => db,
+			None if !audio.channel_ready(channel) => return None,
+			None => {
+				// Magnitudes of this tile were evicted, compute the column under the
+				// pointer instead, once for as long as the pointer stays on it
+				if self
+					.hover_column
+					.as_ref()
+					.is_none_or(|(k, c, _)| (*k, *c) != (key, column))
+				{
+					let start = key.start_ms() + column as f64 * key.ms_per_column();
+					let magnitudes = self.spectrogram.compute_view_columns(
+						&audio,
+						channel,
+						key.view,
+						start,
+						key.ms_per_column(),
+						1,
+					);
+					self.hover_column = magnitudes
+						.into_iter()
+						.next()
+						.map(|magnitudes| (key, column, magnitudes));
+				}
+
+				*self.hover_column.as_ref()?.2.get(bin)?
+			},
+		};
+
+		Some(HoverReadout {
+			ms,
+			position: musical_position(&self.timing_points.read().unwrap(), ms),
+			frequency,
+			db,
+			note: note_name(frequency),
+		})
+	}
+
+	/// Splits the spectrogram area into one row per displayed channel
+	pub fn spectrogram_rows(&self, rect: Rect) -> Vec<(Channel, Rect)> {
+		let channels = self.channel_source.channels();
+		let row_height = rect.height() / channels.len() as f32;
+
+		channels
+			.iter()
+			.enumerate()
+			.map(|(i, &channel)| {
+				let top = rect.top() + row_height * i as f32;
+				let row = Rect::from_min_max(
+					egui::pos2(rect.left(), top),
+					egui::pos2(rect.right(), top + row_height),
+				);
+				(channel, row)
+			})
+			.collect()
+	}
 
-		let freq_bins = self.spectrogram.fft_size / 2;
+	pub fn update_palette(&mut self) {
+		self.spectrogram_palette = Palette::new(
+			&self.spectrogram_colormap,
+			self.colormap_reverse,
+			self.colormap_gamma,
+		);
+	}
 
-		let mut image = ColorImage::filled([spec_width as _, height], Default::default());
+	/// Range used for coloring, which is either set by hand or derived from the magnitudes
+	pub fn db_range(&self) -> (f32, f32) {
+		let global = self
+			.spectrum_stats
+			.as_ref()
+			.and_then(|stats| stats.auto_range(self.whitening));
 
-		for (x, column) in columns.iter().enumerate() {
-			for y in 0..height {
-				let norm_y = (height - 1 - y) as f32 / height as f32;
-				let bin_float = norm_y * (freq_bins - 1) as f32;
-				let bin_lo = bin_float.floor() as usize;
-				let bin_hi = (bin_lo + 1).min(column.len() - 1);
-				let frac = bin_float - bin_lo as f32;
+		let auto = match self.db_range_mode {
+			DbRangeMode::Manual => None,
+			DbRangeMode::AutoGlobal => global,
+			DbRangeMode::AutoVisible => self.visible_db_range.or(global),
+		};
 
-				let value = column[bin_lo] * (1. - frac) + column[bin_hi] * frac;
-				let color = self.spectrogram_colormap.get_color(value);
+		auto.unwrap_or((self.min_db, self.max_db))
+	}
 
-				image[(x, y)] = color;
-			}
+	pub fn spectrogram_whitening(&self) -> Option<Whitening> {
+		if !self.whitening {
+			return None;
 		}
 
-		let texture = ctx.load_texture("spectrogram", image, egui::TextureOptions::LINEAR);
+		self.spectrum_stats
+			.as_ref()
+			.map(|stats| stats.whitening.clone())
+	}
 
-		let cached = CachedSpectrogram::new(
-			texture,
-			spec_start,
-			spec_end,
-			self.fft_size,
-			self.min_db,
-			self.max_db,
+	/// Updates the visible dB range once the visible tiles change, using the
+	/// magnitudes of tiles rendered so far. The range is recomputed every frame
+	/// until all of them have contributed.
+	fn update_visible_db_range(&mut self, keys: &[TileKey]) {
+		if self.db_range_mode != DbRangeMode::AutoVisible || self.visible_db_keys == keys {
+			return;
+		}
+
+		let whitening = self.spectrogram_whitening();
+		let histogram = self.spectrogram_tiles.histogram(keys, whitening.as_ref());
+
+		if !histogram.is_empty() {
+			self.visible_db_range = histogram.auto_range();
+		}
+
+		if self.spectrogram_tiles.has_magnitudes(keys) {
+			self.visible_db_keys = keys.to_vec();
+		}
+	}
+
+	pub fn generate_spectrogram(&mut self, ctx: &egui::Context, width: f32) -> Vec<VisibleTile> {
+		let Some(duration) = self.audio_data.as_ref().map(|data| data.duration) else {
+			return vec![];
+		};
+
+		if self.fft_size != self.spectrogram.fft_size {
+			self.spectrogram = Spectrogram::new(self.fft_size);
+			self.compute_spectrum_stats();
+			self.compute_onsets();
+		}
+
+		let (vis_start, vis_end) = self.timeline.visible_range(width);
+		let vis_end = vis_end.min(duration);
+
+		let keys = self.spectrogram_tiles.begin_frame(
+			vis_start,
+			vis_end,
 			self.timeline.pixels_per_second,
+			self.fft_size,
+			self.spectrogram_view,
+			self.channel_source.channels(),
 		);
-		let (x_from, x_to) = cached.uv(vis_start, vis_end);
 
-		self.cached_spectrogram = Some(cached);
+		self.update_visible_db_range(&keys);
+
+		let (min_db, max_db) = self.db_range();
+		let style = TileStyle::new(
+			self.spectrogram_palette.clone(),
+			self.spectrogram_whitening(),
+			min_db,
+			max_db,
+		);
+
+		let Some(audio) = self.audio_data.as_ref() else {
+			return vec![];
+		};
+
+		// Channels other than the cached mono mix show up once the audio is decoded
+		let tiles = keys
+			.iter()
+			.filter(|key| audio.channel_ready(key.channel))
+			.map(|&key| {
+				let texture = self.spectrogram_tiles.get_or_render(
+					ctx,
+					&self.spectrogram,
+					audio,
+					key,
+					&style,
+				);
+
+				let (start, end) = (key.start_ms(), key.end_ms());
+				let clipped_end = end.min(audio.duration);
+
+				VisibleTile {
+					channel: key.channel,
+					texture,
+					start,
+					end: clipped_end,
+					u_end: (clipped_end - start) / (end - start),
+				}
+			})
+			.collect();
+
+		if keys.iter().all(|key| audio.channel_ready(key.channel)) {
+			self.spectrogram_tiles
+				.prefetch(ctx, &self.spectrogram, audio, &keys, &style);
+		}
 
-		Some((
-			self.cached_spectrogram.as_ref().unwrap().texture.clone(),
-			x_from,
-			x_to,
-		))
+		tiles
 	}
 }
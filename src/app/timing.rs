@@ -1,5 +1,31 @@
+use egui::{Rect, Ui};
+
 use crate::app::SpectralApp;
-use crate::timing::SnapDivision;
+use crate::timing::{SnapDivision, TimingPoint, beat_ticks, measure_bounds, section_index};
+
+// Distance in pixels within which markers can be grabbed and drags snap
+pub const GRAB_DISTANCE: f32 = 6.;
+const SNAP_DISTANCE: f32 = 10.;
+
+// BPM change per pixel when Alt-dragging a marker
+const BPM_PER_PIXEL: f64 = 0.05;
+
+#[derive(Clone, Copy)]
+pub enum DragMode {
+	Offset,
+	Bpm {
+		origin_x: f32,
+	},
+	/// Moves the beat `beats` after the offset, with the offset pinned
+	Anchor {
+		beats: f64,
+	},
+}
+
+pub struct TimingPointDrag {
+	pub before: TimingPoint,
+	pub mode: DragMode,
+}
 
 impl SpectralApp {
 	pub fn sort_timing_points(&mut self) {
@@ -10,53 +36,206 @@ impl SpectralApp {
 	}
 
 	pub fn get_beat_ticks(&self, start: f64, end: f64) -> Vec<(f64, SnapDivision)> {
-		let mut ticks = vec![];
+		let duration = self
+			.audio_data
+			.as_ref()
+			.map(|data| data.duration)
+			.unwrap_or(f64::MAX);
 
+		beat_ticks(
+			&self.timing_points.read().unwrap(),
+			self.snap_divisor,
+			start,
+			end,
+			duration,
+		)
+	}
+
+	/// Timing point whose marker is under `x`, if any
+	pub fn timing_point_at(&self, x: f32, rect: Rect) -> Option<TimingPoint> {
+		self.timing_points
+			.read()
+			.unwrap()
+			.iter()
+			.map(|tp| (tp, (self.timeline.ms_to_x(tp.offset, rect) - x).abs()))
+			.filter(|(_, distance)| *distance <= GRAB_DISTANCE)
+			.min_by(|a, b| a.1.total_cmp(&b.1))
+			.map(|(tp, _)| *tp)
+	}
+
+	/// Snaps a dragged offset to the closest onset or tick of the previous section
+	fn snap_dragged_offset(&self, id: egui::Id, ms: f64, radius: f64) -> f64 {
 		let timing_points = self.timing_points.read().unwrap();
+		let previous = timing_points
+			.iter()
+			.rposition(|tp| tp.id() != id && tp.offset < ms);
 
-		for (i, tp) in timing_points.iter().enumerate() {
-			let ms_per_beat = tp.ms_per_beat();
-			let ms_per_tick = ms_per_beat / self.snap_divisor as f64;
+		let tick = previous.map(|i| {
+			let (tp, next) = (&timing_points[i], timing_points.get(i + 1));
+			let divisor = self.snap_divisor as f64;
+			tp.ms_at((tp.beats_at(ms, next) * divisor).round() / divisor, next)
+		});
 
-			// TODO: stop rendering at low zoom
+		tick.into_iter()
+			.chain(self.nearest_onset(ms, radius))
+			.filter(|target| (target - ms).abs() <= radius)
+			.min_by(|a, b| (a - ms).abs().total_cmp(&(b - ms).abs()))
+			.unwrap_or(ms)
+	}
 
-			let section_end = if i + 1 < timing_points.len() {
-				timing_points[i + 1].offset
-			} else {
-				self.audio_data
-					.as_ref()
-					.map(|data| data.duration)
-					.unwrap_or(f64::MAX)
-			};
+	fn nearest_onset(&self, ms: f64, radius: f64) -> Option<f64> {
+		self.onsets
+			.as_ref()?
+			.peaks_in(ms - radius, ms + radius)
+			.iter()
+			.copied()
+			.min_by(|a, b| (a - ms).abs().total_cmp(&(b - ms).abs()))
+	}
+
+	/// Picks the beat closest to `ms` in the section containing it, at least one
+	/// beat after the section's offset
+	fn anchor_at(&self, ms: f64) -> Option<(TimingPoint, f64)> {
+		let timing_points = self.timing_points.read().unwrap();
+		let idx = section_index(&timing_points, ms)?;
+		let tp = timing_points[idx];
+		let beats = tp.beats_at(ms, timing_points.get(idx + 1)).round().max(1.);
+
+		Some((tp, beats))
+	}
 
-			let tick_start = start.max(tp.offset);
-			let tick_end = end.min(section_end);
+	/// Dragging a marker moves its offset, Alt-dragging changes its BPM instead. In
+	/// anchor mode, dragging anywhere else moves the nearest beat and rescales the
+	/// section around its offset. The whole drag is recorded as a single history entry.
+	pub fn handle_timing_point_drag(&mut self, ui: &mut Ui, rect: Rect, response: &egui::Response) {
+		if self.timing_point_drag.is_none()
+			&& let Some(pos) = response.hover_pos()
+			&& self.timing_point_at(pos.x, rect).is_some()
+		{
+			ui.ctx().set_cursor_icon(egui::CursorIcon::ResizeHorizontal);
+		}
 
-			if tick_start >= tick_end {
-				continue;
+		if response.drag_started_by(egui::PointerButton::Primary)
+			&& self.scrub_drag.is_none()
+			&& let Some(origin) = ui.input(|i| i.pointer.press_origin())
+		{
+			if let Some(tp) = self.timing_point_at(origin.x, rect) {
+				self.timing_point_drag = Some(TimingPointDrag {
+					before: tp,
+					mode: if ui.input(|i| i.modifiers.alt) {
+						DragMode::Bpm { origin_x: origin.x }
+					} else {
+						DragMode::Offset
+					},
+				});
+			} else if self.anchor_mode
+				&& let Some((tp, beats)) = self.anchor_at(self.timeline.x_to_ms(origin.x, rect))
+			{
+				self.timing_point_drag = Some(TimingPointDrag {
+					before: tp,
+					mode: DragMode::Anchor { beats },
+				});
 			}
+		}
 
-			let beats_from_start = ((tick_start - tp.offset) / ms_per_tick).floor() as i64;
-			let first_tick_ms = tp.offset + (beats_from_start as f64 * ms_per_tick);
+		let Some(drag) = &self.timing_point_drag else {
+			return;
+		};
+		let (before, mode) = (drag.before, drag.mode);
 
-			let mut tick_ms = first_tick_ms;
-			let mut tick_count = beats_from_start;
+		if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+			self.replace_timing_point(before);
+			self.sort_timing_points();
+			self.timing_point_drag = None;
+			return;
+		}
 
-			while tick_ms <= tick_end {
-				if tick_ms >= tick_start && tick_ms < section_end {
-					let in_beat = tick_count.rem_euclid(self.snap_divisor);
-					let beat_num = tick_count.div_euclid(self.snap_divisor);
-					let in_measure = beat_num.rem_euclid(tp.signature.0 as i64);
+		if let Some(pos) = response.interact_pointer_pos() {
+			let mut after = before;
+			let ms = self.timeline.x_to_ms(pos.x, rect).max(0.);
+			let radius = self.timeline.x_to_ms(pos.x + SNAP_DISTANCE, rect) - ms;
 
-					let snap = SnapDivision::from_tick(in_beat, self.snap_divisor, in_measure);
+			match mode {
+				DragMode::Offset => {
+					let ms = if self.snap_to_tick {
+						self.snap_dragged_offset(before.id(), ms, radius)
+					} else {
+						ms
+					};
+					after.offset = ms.round();
+				},
+				DragMode::Bpm { origin_x } => {
+					let bpm = before.bpm + (pos.x - origin_x) as f64 * BPM_PER_PIXEL;
+					after.bpm = ((bpm * 100.).round() / 100.).clamp(1., 999.);
+				},
+				DragMode::Anchor { beats } => {
+					let ms = if self.snap_to_tick {
+						self.nearest_onset(ms, radius).unwrap_or(ms)
+					} else {
+						ms
+					};
 
-					ticks.push((tick_ms, snap));
-				}
-				tick_count += 1;
-				tick_ms = tp.offset + (tick_count as f64 * ms_per_tick);
+					if ms > before.offset {
+						let next = self
+							.timing_point_section(before.id())
+							.and_then(|(_, next)| next);
+						let bpm = before.bpm_for_beat(beats, ms, next.as_ref());
+						after.bpm = ((bpm * 100.).round() / 100.).clamp(1., 999.);
+					}
+				},
 			}
+
+			// Kept sorted during the drag so the tick grid stays correct
+			self.replace_timing_point(after);
+			self.sort_timing_points();
+		}
+
+		if response.drag_stopped() {
+			self.timing_point_drag = None;
+			self.finish_timing_point_edit(before);
 		}
+	}
+
+	/// Timing point with `id` and the one after it, which ends its section
+	pub fn timing_point_section(&self, id: egui::Id) -> Option<(TimingPoint, Option<TimingPoint>)> {
+		let timing_points = self.timing_points.read().unwrap();
+		let idx = timing_points.iter().position(|tp| tp.id() == id)?;
+		Some((timing_points[idx], timing_points.get(idx + 1).copied()))
+	}
+
+	fn replace_timing_point(&mut self, replacement: TimingPoint) {
+		if let Some(tp) = self
+			.timing_points
+			.write()
+			.unwrap()
+			.iter_mut()
+			.find(|tp| tp.id() == replacement.id())
+		{
+			*tp = replacement;
+		}
+	}
+
+	/// Closest beat tick to `ms` that is at most `radius` ms away
+	pub fn nearest_beat_tick(&self, ms: f64, radius: f64) -> Option<f64> {
+		self.get_beat_ticks(ms - radius, ms + radius)
+			.into_iter()
+			.map(|(tick, _)| tick)
+			.min_by(|a, b| (a - ms).abs().total_cmp(&(b - ms).abs()))
+	}
+
+	/// Loops the measure under the playhead, or stops looping if it already is
+	pub fn toggle_measure_loop(&mut self) {
+		let position = self.audio_player.get_audible_position_ms();
+		let Some((start, end)) = measure_bounds(&self.timing_points.read().unwrap(), position)
+		else {
+			return;
+		};
+
+		let looped = self
+			.audio_player
+			.get_loop()
+			.is_some_and(|(s, e)| (s - start).abs() < 1. && (e - end).abs() < 1.);
 
-		ticks
+		self.audio_player
+			.set_loop((!looped).then_some((start, end)));
 	}
 }
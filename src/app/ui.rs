@@ -1,12 +1,16 @@
 use egui::{Color32, Pos2, Rect, Sense, Stroke, StrokeKind, Ui};
 
+use crate::app::timing::{DragMode, TimingPointDrag};
 use crate::app::{SpectralApp, TimingMode};
 use crate::colors::{
-	COLOR_AXES_STROKE, COLOR_AXES_TEXT, COLOR_CURSOR, COLOR_PLAYHEAD, COLOR_SCROLL,
+	COLOR_AXES_STROKE, COLOR_AXES_TEXT, COLOR_BOX_SELECT, COLOR_CURSOR, COLOR_LOOP_EDGE,
+	COLOR_LOOP_REGION, COLOR_ONSET, COLOR_ONSET_PEAK, COLOR_PLAYHEAD, COLOR_SCROLL,
 	COLOR_SCROLL_OUTLINE, COLOR_SCROLL_OUTLINE_HOVER, COLOR_SCROLL_THUMB, COLOR_SCROLL_THUMB_HOVER,
-	COLOR_TIMING_POINT, COLOR_TIMING_POINT_TEMPORARY,
+	COLOR_TEXT_HIGHLIGHT, COLOR_TIMING_POINT, COLOR_TIMING_POINT_SELECTED,
+	COLOR_TIMING_POINT_TEMPORARY, COLOR_WAVEFORM, COLOR_WAVEFORM_RMS,
 };
 use crate::util::format_time;
+use crate::waveform::WaveformLane;
 
 impl SpectralApp {
 	pub fn draw_ruler(&self, ui: &mut Ui, rect: Rect) {
@@ -25,6 +29,8 @@ impl SpectralApp {
 		.copied()
 		.unwrap_or(60000.);
 
+		self.draw_loop_region(ui, rect);
+
 		let start_tick = (vis_start / interval).floor() as i64;
 		let end_tick = (vis_end / interval).ceil() as i64;
 
@@ -56,16 +62,25 @@ impl SpectralApp {
 		if let Some(data) = &self.audio_data {
 			let max_freq = data.sample_rate as f32 / 2.;
 
-			let freqs = [
-				2000., 4000., 6000., 8000., 10000., 12000., 14000., 16000., 18000., 20000.,
-			]
-			.into_iter()
-			.filter(|&f| f <= max_freq);
+			for (_, row) in self.spectrogram_rows(rect) {
+				// Stacked rows only get every other label
+				let step = if row.height() < 250. { 2 } else { 1 };
+
+				let freqs = [
+					2000., 4000., 6000., 8000., 10000., 12000., 14000., 16000., 18000., 20000.,
+				]
+				.into_iter()
+				.skip(step - 1)
+				.step_by(step)
+				.filter(|&f| f <= max_freq);
 
-			for freq in freqs {
-				let y = rect.bottom() - (freq / max_freq) * rect.height();
+				for freq in freqs {
+					let y = self.frequency_to_y(freq, max_freq, row);
+
+					if y < row.top() || y > row.bottom() {
+						continue;
+					}
 
-				if y >= rect.top() && y <= rect.bottom() {
 					painter.line_segment(
 						[Pos2::new(rect.right() - 4.0, y), Pos2::new(rect.right(), y)],
 						Stroke::new(1., COLOR_AXES_STROKE),
@@ -89,25 +104,64 @@ impl SpectralApp {
 		}
 	}
 
+	/// Splits the timeline into the spectrogram area and the waveform lane, if shown
+	pub fn timeline_lanes(&self, rect: Rect) -> (Rect, Option<Rect>) {
+		match self.waveform_lane {
+			WaveformLane::Off => (rect, None),
+			WaveformLane::Overlay => (rect, Some(rect)),
+			WaveformLane::Below => {
+				let (top, bottom) =
+					rect.split_top_bottom_at_y(rect.bottom() - (rect.height() * 0.3).round());
+				(top, Some(bottom))
+			},
+		}
+	}
+
 	pub fn draw_timeline(&mut self, ui: &mut Ui, rect: Rect) {
-		self.draw_spectrogram(ui, rect);
+		let (spectrogram_rect, waveform_rect) = self.timeline_lanes(rect);
+
+		self.draw_spectrogram(ui, spectrogram_rect);
+
+		if let Some(waveform_rect) = waveform_rect {
+			self.draw_waveform(ui, waveform_rect);
+		}
+
+		if self.show_onsets {
+			self.draw_onsets(ui, rect);
+		}
 
 		let painter = ui.painter_at(rect);
 
+		self.draw_loop_region(ui, rect);
 		self.draw_beat_ticks(ui, rect);
 		self.draw_timing_points(ui, rect);
+		self.draw_beat_markers(ui, rect);
 		self.draw_playhead(ui, rect);
 		self.draw_cursor(ui, rect);
+		self.draw_box_select(ui, rect);
+
+		if !self.audio_loading {
+			self.draw_hover_readout(ui, spectrogram_rect);
+		}
 
 		if self.audio_loading {
 			painter.rect_filled(rect, 0., Color32::from_rgba_premultiplied(0, 0, 0, 120));
 			painter.text(
 				rect.center(),
 				egui::Align2::CENTER_CENTER,
-				"Loading audio...",
+				format!("Loading audio... {:.0}%", self.audio_load_progress * 100.),
 				egui::FontId::proportional(14.),
 				Color32::WHITE,
 			);
+
+			let button_rect =
+				Rect::from_center_size(rect.center() + egui::vec2(0., 28.), egui::vec2(80., 22.));
+			let cancel = ui.put(button_rect, egui::Button::new("Cancel")).clicked()
+				|| ui.input(|i| i.key_pressed(egui::Key::Escape));
+
+			if cancel {
+				self.cancel_audio_loading();
+			}
 		}
 	}
 
@@ -118,14 +172,179 @@ impl SpectralApp {
 			return;
 		}
 
-		if let Some((texture, x_from, x_to)) =
-			self.generate_spectrogram(ui.ctx(), rect.width() as _, rect.height() as _)
-		{
-			let uv = Rect::from_min_max(Pos2::new(x_from as _, 0.), Pos2::new(x_to as _, 1.));
-			painter.image(texture.id(), rect, uv, Color32::WHITE);
+		let rows = self.spectrogram_rows(rect);
+
+		for tile in self.generate_spectrogram(ui.ctx(), rect.width()) {
+			let Some(&(_, row)) = rows.iter().find(|(c, _)| *c == tile.channel) else {
+				continue;
+			};
+
+			let tile_rect = Rect::from_min_max(
+				Pos2::new(self.timeline.ms_to_x(tile.start, rect), row.top()),
+				Pos2::new(self.timeline.ms_to_x(tile.end, rect), row.bottom()),
+			);
+			let uv = Rect::from_min_max(Pos2::new(0., 0.), Pos2::new(tile.u_end as _, 1.));
+			painter.image(tile.texture.id(), tile_rect, uv, Color32::WHITE);
+		}
+
+		for (_, row) in rows.iter().skip(1) {
+			painter.line_segment(
+				[row.left_top(), row.right_top()],
+				Stroke::new(1., COLOR_AXES_STROKE),
+			);
+		}
+	}
+
+	pub fn draw_waveform(&self, ui: &mut Ui, rect: Rect) {
+		let (Some(waveform), Some(audio)) = (&self.waveform, &self.audio_data) else {
+			return;
+		};
+
+		let painter = ui.painter_at(rect);
+
+		let overlay = self.waveform_lane == WaveformLane::Overlay;
+		let with_alpha = |color: Color32| {
+			let (r, g, b, _) = color.to_tuple();
+			Color32::from_rgba_unmultiplied(r, g, b, if overlay { 110 } else { 255 })
+		};
+
+		if !overlay {
+			painter.line_segment(
+				[rect.left_top(), rect.right_top()],
+				Stroke::new(1., COLOR_AXES_STROKE),
+			);
+		}
+
+		let channels = self.channel_source.channels();
+		let lane_height = rect.height() / channels.len() as f32;
+
+		let frames_per_pixel = audio.sample_rate as f64 / self.timeline.pixels_per_second;
+		let start_frame = self.timeline.offset / 1000. * audio.sample_rate as f64;
+
+		for (i, &channel) in channels.iter().enumerate() {
+			let center = rect.top() + lane_height * (i as f32 + 0.5);
+			let scale = lane_height / 2. * 0.95;
+
+			let peaks = waveform.peaks(
+				channel,
+				start_frame,
+				frames_per_pixel,
+				rect.width().ceil() as usize,
+			);
+
+			for (x, peak) in peaks.into_iter().enumerate() {
+				let Some(peak) = peak else {
+					continue;
+				};
+
+				let x = rect.left() + x as f32 + 0.5;
+				let top = center - peak.max * scale;
+				let bottom = (center - peak.min * scale).max(top + 1.);
+
+				painter.line_segment(
+					[Pos2::new(x, top), Pos2::new(x, bottom)],
+					Stroke::new(1., with_alpha(COLOR_WAVEFORM)),
+				);
+
+				if self.waveform_rms {
+					let rms = peak.rms() * scale;
+					painter.line_segment(
+						[
+							Pos2::new(x, (center - rms).max(top)),
+							Pos2::new(x, (center + rms).min(bottom)),
+						],
+						Stroke::new(1., with_alpha(COLOR_WAVEFORM_RMS)),
+					);
+				}
+			}
+		}
+	}
+
+	pub fn draw_onsets(&self, ui: &mut Ui, rect: Rect) {
+		let Some(onsets) = &self.onsets else {
+			return;
+		};
+
+		let painter = ui.painter_at(rect);
+
+		let height = rect.height() * 0.4;
+		let ms_per_pixel = 1. / self.timeline.pixels_per_ms();
+
+		let points: Vec<_> = (0..rect.width().ceil() as usize)
+			.filter_map(|x| {
+				let start = self.timeline.offset + x as f64 * ms_per_pixel;
+				let value = onsets.max_in(start, start + ms_per_pixel)?;
+				Some(Pos2::new(
+					rect.left() + x as f32,
+					rect.bottom() - value * height,
+				))
+			})
+			.collect();
+
+		painter.add(egui::Shape::line(points, Stroke::new(1., COLOR_ONSET)));
+
+		let (start, end) = self.timeline.visible_range(rect.width());
+		for &peak in onsets.peaks_in(start, end) {
+			let x = self.timeline.ms_to_x(peak, rect);
+			let value = onsets.max_in(peak - onsets.hop_ms(), peak).unwrap_or(0.);
+
+			painter.circle_filled(
+				Pos2::new(x, rect.bottom() - value * height),
+				2.5,
+				COLOR_ONSET_PEAK,
+			);
 		}
 	}
 
+	pub fn draw_hover_readout(&mut self, ui: &mut Ui, rect: Rect) {
+		let Some(pos) = ui.input(|i| i.pointer.hover_pos()) else {
+			return;
+		};
+
+		let Some(readout) = self.hover_readout(pos, rect) else {
+			return;
+		};
+
+		let mut lines = vec![format!(
+			"{} ({:.0} ms)",
+			format_time(readout.ms),
+			readout.ms
+		)];
+
+		if let Some((measure, beat)) = readout.position {
+			lines.push(format!("Measure {}, beat {:.2}", measure, beat));
+		}
+
+		lines.push(match &readout.note {
+			Some((note, cents)) => format!("{:.0} Hz, {} {:+}c", readout.frequency, note, cents),
+			None => format!("{:.0} Hz", readout.frequency),
+		});
+		lines.push(format!("{:.1} dB", readout.db));
+
+		let painter = ui.painter_at(rect);
+		let galley = painter.layout_no_wrap(
+			lines.join("\n"),
+			egui::FontId::monospace(11.),
+			Color32::WHITE,
+		);
+
+		let padding = egui::vec2(6., 4.);
+		let size = galley.size() + padding * 2.;
+
+		// Keep the readout inside the spectrogram, flipping it to the other side of the pointer
+		let mut min = pos + egui::vec2(14., 14.);
+		if min.x + size.x > rect.right() {
+			min.x = pos.x - 14. - size.x;
+		}
+		if min.y + size.y > rect.bottom() {
+			min.y = pos.y - 14. - size.y;
+		}
+
+		let box_rect = Rect::from_min_size(min, size);
+		painter.rect_filled(box_rect, 3., Color32::from_black_alpha(190));
+		painter.galley(box_rect.min + padding, galley, Color32::WHITE);
+	}
+
 	pub fn draw_cursor(&mut self, ui: &mut Ui, rect: Rect) {
 		let ms = if self.snap_to_tick {
 			self.snap_ms
@@ -143,10 +362,64 @@ impl SpectralApp {
 		}
 	}
 
+	pub fn draw_box_select(&self, ui: &mut Ui, rect: Rect) {
+		let (Some((start, y)), Some(pos)) = (
+			self.box_select_start,
+			ui.input(|i| i.pointer.interact_pos()),
+		) else {
+			return;
+		};
+
+		let start = Pos2::new(self.timeline.ms_to_x(start, rect), y);
+		let selection = Rect::from_two_pos(start, pos).intersect(rect);
+
+		let painter = ui.painter_at(rect);
+		painter.rect_filled(selection, 0., COLOR_BOX_SELECT);
+		painter.rect_stroke(
+			selection,
+			0.,
+			Stroke::new(1., COLOR_TEXT_HIGHLIGHT),
+			StrokeKind::Inside,
+		);
+	}
+
+	pub fn draw_loop_region(&self, ui: &mut Ui, rect: Rect) {
+		let Some((start, end)) = self.audio_player.get_loop() else {
+			return;
+		};
+
+		let painter = ui.painter_at(rect);
+		let (left, right) = (
+			self.timeline.ms_to_x(start, rect),
+			self.timeline.ms_to_x(end, rect),
+		);
+
+		painter.rect_filled(
+			Rect::from_x_y_ranges(left..=right, rect.y_range()),
+			0.,
+			COLOR_LOOP_REGION,
+		);
+
+		for x in [left, right] {
+			painter.line_segment(
+				[Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+				Stroke::new(1., COLOR_LOOP_EDGE),
+			);
+		}
+	}
+
 	pub fn draw_playhead(&self, ui: &mut Ui, rect: Rect) {
+		if let Some(ms) = self.audio_player.get_preview_position_ms() {
+			let x = self.timeline.ms_to_x(ms, rect);
+			ui.painter_at(rect).line_segment(
+				[Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+				Stroke::new(1., COLOR_PLAYHEAD.gamma_multiply(0.6)),
+			);
+		}
+
 		let x = self
 			.timeline
-			.ms_to_x(self.audio_player.get_position_ms(), rect);
+			.ms_to_x(self.audio_player.get_audible_position_ms(), rect);
 
 		if x >= rect.left() && x <= rect.right() {
 			ui.painter_at(rect).line_segment(
@@ -172,9 +445,15 @@ impl SpectralApp {
 		for tp in self.timing_points.read().unwrap().iter() {
 			let x = self.timeline.ms_to_x(tp.offset, rect);
 			if x >= rect.left() && x <= rect.right() {
+				let color = if self.is_selected(tp) {
+					COLOR_TIMING_POINT_SELECTED
+				} else {
+					COLOR_TIMING_POINT
+				};
+
 				ui.painter_at(rect).line_segment(
 					[Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
-					Stroke::new(2., COLOR_TIMING_POINT),
+					Stroke::new(2., color),
 				);
 
 				let tri = vec![
@@ -183,11 +462,8 @@ impl SpectralApp {
 					Pos2::new(x, rect.top() + 12.),
 				];
 
-				ui.painter_at(rect).add(egui::Shape::convex_polygon(
-					tri,
-					COLOR_TIMING_POINT,
-					Stroke::NONE,
-				));
+				ui.painter_at(rect)
+					.add(egui::Shape::convex_polygon(tri, color, Stroke::NONE));
 			}
 		}
 
@@ -210,6 +486,28 @@ impl SpectralApp {
 			},
 			TimingMode::Idle => {},
 		}
+
+		if let Some(TimingPointDrag {
+			before,
+			mode: DragMode::Anchor { beats },
+		}) = self.timing_point_drag
+			&& let Some((tp, next)) = self.timing_point_section(before.id())
+		{
+			let x = self.timeline.ms_to_x(tp.ms_at(beats, next.as_ref()), rect);
+
+			ui.painter_at(rect).line_segment(
+				[Pos2::new(x, rect.top()), Pos2::new(x, rect.bottom())],
+				Stroke::new(2., COLOR_TIMING_POINT_TEMPORARY),
+			);
+
+			ui.painter_at(rect).text(
+				Pos2::new(x, rect.top() + 5.),
+				egui::Align2::CENTER_TOP,
+				format!("ANCHOR +{} ({:.2} BPM)", beats, tp.bpm),
+				egui::FontId::proportional(9.),
+				COLOR_TIMING_POINT_TEMPORARY,
+			);
+		}
 	}
 
 	pub fn draw_beat_ticks(&mut self, ui: &mut Ui, rect: Rect) {
@@ -249,6 +547,20 @@ impl SpectralApp {
 				);
 			}
 		}
+
+		if self.snap_to_tick
+			&& self.snap_to_onsets
+			&& let Some(mx) = mouse_x
+			&& let Some(onsets) = &self.onsets
+		{
+			for &peak in onsets.peaks_in(start, end) {
+				let dist = (self.timeline.ms_to_x(peak, rect) - mx).abs();
+				if dist < closest_dist {
+					closest_dist = dist;
+					self.snap_ms = Some(peak);
+				}
+			}
+		}
 	}
 
 	pub fn draw_scrollbar(&mut self, ui: &mut Ui, rect: Rect) {
@@ -262,7 +574,8 @@ impl SpectralApp {
 		};
 
 		let playhead_x = rect.left()
-			+ (self.audio_player.get_position_ms() / audio_data.duration) as f32 * rect.width();
+			+ (self.audio_player.get_audible_position_ms() / audio_data.duration) as f32
+				* rect.width();
 
 		painter.line_segment(
 			[
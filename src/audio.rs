@@ -1,56 +1,368 @@
-use std::fs::File;
-use std::io::BufReader;
+use std::f32::consts::PI;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
-use std::sync::Arc;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicUsize, Ordering};
-use std::time::Duration;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 use eyre::Result;
 use rodio::buffer::SamplesBuffer;
 use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use serde::{Deserialize, Serialize};
 
+use crate::cache::{DiskCache, read_f32s, write_f32s};
 use crate::settings::SettingsManager;
+
+const AUDIO_CACHE_MAGIC: &[u8; 4] = b"SPA2";
+
+// Scrubbing plays back-to-back Hann-windowed grains of this length
+const SCRUB_GRAIN_MS: f64 = 80.;
+const SCRUB_QUEUED_GRAINS: usize = 2;
+
+// Decoded samples between progress reports and cancellation checks
+const PROGRESS_INTERVAL: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Channel {
+	Mono,
+	Left,
+	Right,
+	Mid,
+	Side,
+}
+
+impl Channel {
+	/// Derives this channel's sample from an interleaved frame,
+	/// treating mono audio as identical left and right channels
+	pub fn mix(self, frame: &[f32]) -> f32 {
+		let left = frame[0];
+		let right = frame.get(1).copied().unwrap_or(left);
+
+		match self {
+			Self::Mono => frame.iter().sum::<f32>() / frame.len() as f32,
+			Self::Left => left,
+			Self::Right => right,
+			Self::Mid => (left + right) / 2.,
+			Self::Side => (left - right) / 2.,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ChannelSource {
+	Mono,
+	Left,
+	Right,
+	Mid,
+	Side,
+	StackedLeftRight,
+	StackedMidSide,
+}
+
+impl ChannelSource {
+	pub fn all() -> &'static [Self] {
+		&[
+			Self::Mono,
+			Self::Left,
+			Self::Right,
+			Self::Mid,
+			Self::Side,
+			Self::StackedLeftRight,
+			Self::StackedMidSide,
+		]
+	}
+
+	/// Channels shown by this source, from top to bottom
+	pub fn channels(self) -> &'static [Channel] {
+		match self {
+			Self::Mono => &[Channel::Mono],
+			Self::Left => &[Channel::Left],
+			Self::Right => &[Channel::Right],
+			Self::Mid => &[Channel::Mid],
+			Self::Side => &[Channel::Side],
+			Self::StackedLeftRight => &[Channel::Left, Channel::Right],
+			Self::StackedMidSide => &[Channel::Mid, Channel::Side],
+		}
+	}
+
+	/// Single channel used for analysis, such as onset detection
+	pub fn analysis_channel(self) -> Channel {
+		match self {
+			Self::StackedLeftRight => Channel::Mono,
+			Self::StackedMidSide => Channel::Mid,
+			_ => self.channels()[0],
+		}
+	}
+}
+
+impl Display for ChannelSource {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{}",
+			match self {
+				Self::Mono => "Mono",
+				Self::Left => "Left",
+				Self::Right => "Right",
+				Self::Mid => "Mid",
+				Self::Side => "Side",
+				Self::StackedLeftRight => "Left / Right",
+				Self::StackedMidSide => "Mid / Side",
+			}
+		)
+	}
+}
+
+impl FromStr for ChannelSource {
+	type Err = eyre::Report;
+
+	fn from_str(s: &str) -> Result<Self> {
+		Ok(match s.to_lowercase().as_str() {
+			"mono" => Self::Mono,
+			"left" | "l" => Self::Left,
+			"right" | "r" => Self::Right,
+			"mid" | "m" => Self::Mid,
+			"side" | "s" => Self::Side,
+			"lr" | "left-right" => Self::StackedLeftRight,
+			"ms" | "mid-side" => Self::StackedMidSide,
+			_ => eyre::bail!("unknown channel source: {}", s),
+		})
+	}
+}
+
+/// Keeps track of how far the decoder has read into the file
+struct ProgressReader {
+	inner: Cursor<Vec<u8>>,
+	position: Arc<AtomicUsize>,
+}
+
+impl Read for ProgressReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = self.inner.read(buf)?;
+		self.position
+			.store(self.inner.position() as usize, Ordering::Relaxed);
+		Ok(read)
+	}
+}
+
+impl Seek for ProgressReader {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		let position = self.inner.seek(pos)?;
+		self.position.store(position as usize, Ordering::Relaxed);
+		Ok(position)
+	}
+}
+
+/// Decoded audio, stored once as interleaved samples. Single channel views are
+/// derived on first use, and mono files share the interleaved buffer for all of them.
 pub struct AudioData {
-	pub samples: Arc<Vec<f32>>,
-	pub mono_samples: Arc<Vec<f32>>,
+	pub hash: u64,
+	// Set once decoding is done, the mono mix may be known before from the cache
+	samples: OnceLock<Arc<Vec<f32>>>,
+	channel_views: [OnceLock<Arc<Vec<f32>>>; 5],
 	pub sample_rate: u32,
 	pub channels: u16,
 	pub duration: f64,
 }
 
 impl AudioData {
-	pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-		let file = File::open(path)?;
-		let source = Decoder::new(BufReader::new(file))?;
+	/// Decodes an audio file. If its mono mix is in the cache, `cached` receives the
+	/// audio before the rest is decoded, for analysis to start right away.
+	/// `progress` receives the decoded fraction of the file, and setting `cancel`
+	/// aborts decoding.
+	pub fn load_from_file<P: AsRef<Path>>(
+		path: P,
+		cache: &DiskCache,
+		progress: impl FnMut(f32),
+		cancel: &AtomicBool,
+		cached: impl FnOnce(Arc<Self>),
+	) -> Result<Arc<Self>> {
+		let bytes = fs::read(path)?;
+		let hash = DiskCache::hash(&bytes);
+		let cache_name = format!("{:016x}.audio", hash);
+
+		let Some(data) = cache
+			.open(&cache_name)
+			.and_then(|mut reader| Self::read_cached(&mut reader, hash).ok())
+		else {
+			let data = Arc::new(Self::decode(hash, bytes, progress, cancel)?);
+
+			// Only the mono mix is kept, the other channels are cheap to derive
+			// once decoded and would multiply the size of the entry
+			let mono = data.channel_samples(Channel::Mono);
+			let (sample_rate, channels) = (data.sample_rate, data.channels);
+			cache.write(cache_name, move |w| {
+				w.write_all(AUDIO_CACHE_MAGIC)?;
+				w.write_all(&sample_rate.to_le_bytes())?;
+				w.write_all(&channels.to_le_bytes())?;
+				w.write_all(&(mono.len() as u64).to_le_bytes())?;
+				write_f32s(w, &mono)
+			});
+
+			return Ok(data);
+		};
+
+		let data = Arc::new(data);
+		if data.samples().is_some() {
+			return Ok(data);
+		}
+
+		cached(data.clone());
+
+		match Self::decode(hash, bytes, progress, cancel) {
+			Ok(decoded) => {
+				let _ = data.samples.set(decoded.samples().unwrap());
+				Ok(data)
+			},
+			Err(e) => {
+				// The audio is dropped by the caller, views still waiting for the
+				// samples get the mono mix on every channel rather than hanging
+				let mono = data.channel_samples(Channel::Mono);
+				let channels = data.channels as usize;
+				let _ = data.samples.set(Arc::new(
+					mono.iter()
+						.flat_map(|&sample| std::iter::repeat_n(sample, channels))
+						.collect(),
+				));
+				Err(e)
+			},
+		}
+	}
+
+	fn decode(
+		hash: u64,
+		bytes: Vec<u8>,
+		mut progress: impl FnMut(f32),
+		cancel: &AtomicBool,
+	) -> Result<Self> {
+		let total_bytes = bytes.len().max(1);
+		let position = Arc::new(AtomicUsize::new(0));
+		let source = Decoder::new(ProgressReader {
+			inner: Cursor::new(bytes),
+			position: position.clone(),
+		})?;
 
 		let sample_rate = source.sample_rate();
 		let channels = source.channels();
 
-		let samples: Vec<_> = source.convert_samples().collect();
+		let capacity = source.total_duration().map_or(0, |duration| {
+			(duration.as_secs_f64() * sample_rate as f64) as usize * channels as usize
+		});
+		let mut samples = Vec::with_capacity(capacity);
 
-		let mono_samples = if channels > 1 {
-			samples
-				.chunks(channels as usize)
-				.map(|chunk| chunk.iter().sum::<f32>() / channels as f32)
-				.collect()
-		} else {
-			samples.clone()
+		for sample in source.convert_samples() {
+			samples.push(sample);
+
+			if samples.len() % PROGRESS_INTERVAL == 0 {
+				if cancel.load(Ordering::Relaxed) {
+					eyre::bail!("audio loading was cancelled");
+				}
+
+				progress(position.load(Ordering::Relaxed) as f32 / total_bytes as f32);
+			}
+		}
+		samples.shrink_to_fit();
+
+		Ok(Self::from_samples(hash, samples, sample_rate, channels))
+	}
+
+	fn read_cached(reader: &mut impl Read, hash: u64) -> io::Result<Self> {
+		let mut magic = [0u8; 4];
+		reader.read_exact(&mut magic)?;
+		if &magic != AUDIO_CACHE_MAGIC {
+			return Err(io::ErrorKind::InvalidData.into());
+		}
+
+		let mut sample_rate = [0u8; 4];
+		let mut channels = [0u8; 2];
+		let mut len = [0u8; 8];
+		reader.read_exact(&mut sample_rate)?;
+		reader.read_exact(&mut channels)?;
+		reader.read_exact(&mut len)?;
+
+		let mono = read_f32s(reader, u64::from_le_bytes(len) as usize)?;
+		let (sample_rate, channels) = (
+			u32::from_le_bytes(sample_rate),
+			u16::from_le_bytes(channels),
+		);
+
+		// The mono mix of a mono file is the whole of it
+		if channels == 1 {
+			return Ok(Self::from_samples(hash, mono, sample_rate, channels));
+		}
+
+		let data = Self {
+			hash,
+			samples: OnceLock::new(),
+			channel_views: Default::default(),
+			sample_rate,
+			channels,
+			duration: mono.len() as f64 / sample_rate as f64 * 1000.,
 		};
+		let _ = data.channel_views[0].set(Arc::new(mono));
 
-		let duration = (mono_samples.len() as f64 / sample_rate as f64) * 1000.;
+		Ok(data)
+	}
 
-		Ok(Self {
-			samples: Arc::new(samples),
-			mono_samples: Arc::new(mono_samples),
+	pub fn from_samples(hash: u64, samples: Vec<f32>, sample_rate: u32, channels: u16) -> Self {
+		let frames = samples.len() / channels.max(1) as usize;
+		let duration = (frames as f64 / sample_rate as f64) * 1000.;
+
+		Self {
+			hash,
+			samples: OnceLock::from(Arc::new(samples)),
+			channel_views: Default::default(),
 			sample_rate,
 			channels,
 			duration,
+		}
+	}
+
+	/// Interleaved samples, once decoded
+	pub fn samples(&self) -> Option<Arc<Vec<f32>>> {
+		self.samples.get().cloned()
+	}
+
+	/// Whether [`Self::channel_samples`] can return without waiting for decoding
+	pub fn channel_ready(&self, channel: Channel) -> bool {
+		self.samples.get().is_some()
+			|| (channel == Channel::Mono && self.channel_views[0].get().is_some())
+	}
+
+	/// Returns the samples of a single channel, deriving them on first use. Waits for
+	/// decoding to finish unless the channel is already known.
+	pub fn channel_samples(&self, channel: Channel) -> Arc<Vec<f32>> {
+		if self.channels == 1 && channel != Channel::Side {
+			return self.samples.wait().clone();
+		}
+
+		let view = match channel {
+			Channel::Mono => &self.channel_views[0],
+			Channel::Left => &self.channel_views[1],
+			Channel::Right => &self.channel_views[2],
+			Channel::Mid => &self.channel_views[3],
+			Channel::Side => &self.channel_views[4],
+		};
+
+		view.get_or_init(|| {
+			Arc::new(
+				self.samples
+					.wait()
+					.chunks(self.channels as usize)
+					.map(|frame| channel.mix(frame))
+					.collect(),
+			)
 		})
+		.clone()
 	}
 
 	pub fn ms_to_idx(&self, ms: f64) -> usize {
+		let frames = (self.duration / 1000. * self.sample_rate as f64).round() as usize;
 		let frame = ((ms / 1000.) * self.sample_rate as f64) as usize;
-		(frame * self.channels as usize).min(self.samples.len())
+		frame.min(frames) * self.channels as usize
 	}
 
 	pub fn idx_to_ms(&self, idx: usize) -> f64 {
@@ -65,6 +377,8 @@ pub struct SeekableSource {
 	channels: u16,
 	position: Arc<AtomicUsize>,
 	playing: Arc<AtomicBool>,
+	loop_start: Arc<AtomicUsize>,
+	loop_end: Arc<AtomicUsize>,
 }
 
 impl Iterator for SeekableSource {
@@ -75,7 +389,15 @@ impl Iterator for SeekableSource {
 			return Some(0.0);
 		}
 
-		let pos = self.position.fetch_add(1, Ordering::SeqCst);
+		let mut pos = self.position.fetch_add(1, Ordering::SeqCst);
+
+		// Loop bounds are frame aligned, so playback reaches the end exactly
+		let loop_start = self.loop_start.load(Ordering::SeqCst);
+		if pos == self.loop_end.load(Ordering::SeqCst) && pos > loop_start {
+			self.position.store(loop_start + 1, Ordering::SeqCst);
+			pos = loop_start;
+		}
+
 		if pos < self.samples.len() {
 			Some(self.samples[pos])
 		} else {
@@ -111,6 +433,11 @@ pub struct AudioPlayer {
 	handle: OutputStreamHandle,
 	sink: Option<Sink>,
 	pub metronome_sink: Arc<Sink>,
+	// Whether the metronome also clicks 8ths, or 16ths in sections swinging them
+	pub metronome_subdivide: Arc<AtomicBool>,
+	// Previews and scrubbing grains play on their own sink, leaving the playhead alone
+	preview_sink: Option<Sink>,
+	preview: Option<(f64, f64, Instant)>,
 
 	samples: Option<Arc<Vec<f32>>>,
 	pub sample_rate: Arc<AtomicU32>,
@@ -118,8 +445,14 @@ pub struct AudioPlayer {
 	pub position: Arc<AtomicUsize>,
 	pub playing: Arc<AtomicBool>,
 
+	// A-B loop in samples, disabled while the end is not after the start
+	loop_start: Arc<AtomicUsize>,
+	loop_end: Arc<AtomicUsize>,
+
 	duration: f64,
 	volume: f32,
+	// Time between samples being read from the source and being heard
+	output_latency_ms: f64,
 }
 
 impl AudioPlayer {
@@ -134,6 +467,11 @@ impl AudioPlayer {
 			handle,
 			sink: None,
 			metronome_sink,
+			metronome_subdivide: Arc::new(AtomicBool::new(
+				settings.read(|s| s.metronome_subdivide),
+			)),
+			preview_sink: None,
+			preview: None,
 
 			samples: None,
 			sample_rate: Arc::new(AtomicU32::new(41000)),
@@ -141,8 +479,12 @@ impl AudioPlayer {
 			position: Arc::new(AtomicUsize::new(0)),
 			playing: Arc::new(AtomicBool::new(false)),
 
+			loop_start: Arc::new(AtomicUsize::new(0)),
+			loop_end: Arc::new(AtomicUsize::new(0)),
+
 			duration: 0.,
 			volume: settings.read(|s| s.audio_volume),
+			output_latency_ms: settings.read(|s| s.output_latency_ms),
 		})
 	}
 
@@ -150,14 +492,22 @@ impl AudioPlayer {
 		if let Some(sink) = self.sink.take() {
 			sink.stop();
 		}
+		self.preview_sink = None;
+		self.preview = None;
 
-		self.samples = Some(audio_data.samples.clone());
+		// Audio read from the cache can't be played until it is decoded
+		self.samples = audio_data.samples();
 		self.sample_rate
 			.store(audio_data.sample_rate, Ordering::SeqCst);
 		self.channels.store(audio_data.channels, Ordering::SeqCst);
-		self.duration = audio_data.duration;
+		self.duration = if self.samples.is_some() {
+			audio_data.duration
+		} else {
+			0.
+		};
 		self.position.store(0, Ordering::SeqCst);
 		self.playing.store(false, Ordering::SeqCst);
+		self.set_loop(None);
 
 		self.create_sink()?;
 
@@ -172,6 +522,8 @@ impl AudioPlayer {
 				channels: self.channels.load(Ordering::SeqCst),
 				position: self.position.clone(),
 				playing: self.playing.clone(),
+				loop_start: self.loop_start.clone(),
+				loop_end: self.loop_end.clone(),
 			};
 
 			let sink = Sink::try_new(&self.handle)?;
@@ -217,20 +569,156 @@ impl AudioPlayer {
 	}
 
 	pub fn get_position_ms(&self) -> f64 {
-		let pos = self.position.load(Ordering::SeqCst);
-		let frame = pos / self.channels.load(Ordering::SeqCst) as usize;
-		(frame as f64 / self.sample_rate.load(Ordering::SeqCst) as f64) * 1000.
+		self.sample_idx_to_ms(self.position.load(Ordering::SeqCst))
 	}
 
-	pub fn seek_to(&self, ms: f64) {
+	/// Position of the audio currently coming out of the speakers, which lags
+	/// behind the read position by the output latency while playing
+	pub fn get_audible_position_ms(&self) -> f64 {
+		let position = self.get_position_ms();
+
+		if self.is_playing() {
+			(position - self.output_latency_ms).max(0.)
+		} else {
+			position
+		}
+	}
+
+	pub fn set_output_latency(&mut self, latency_ms: f64) {
+		self.output_latency_ms = latency_ms;
+	}
+
+	pub fn get_output_latency(&self) -> f64 {
+		self.output_latency_ms
+	}
+
+	fn ms_to_sample_idx(&self, ms: f64) -> usize {
 		let ms = ms.clamp(0., self.duration);
 		let frame = ((ms / 1000.) * self.sample_rate.load(Ordering::SeqCst) as f64) as usize;
 
 		let sample_idx = frame * self.channels.load(Ordering::SeqCst) as usize;
 		let max_idx = self.samples.as_ref().map(|s| s.len()).unwrap_or(0);
 
+		sample_idx.min(max_idx)
+	}
+
+	fn sample_idx_to_ms(&self, idx: usize) -> f64 {
+		let frame = idx / self.channels.load(Ordering::SeqCst) as usize;
+		(frame as f64 / self.sample_rate.load(Ordering::SeqCst) as f64) * 1000.
+	}
+
+	pub fn seek_to(&self, ms: f64) {
 		self.position
-			.store(sample_idx.min(max_idx), Ordering::SeqCst);
+			.store(self.ms_to_sample_idx(ms), Ordering::SeqCst);
+	}
+
+	/// Makes playback jump back to `start` whenever it reaches `end`, or
+	/// disables looping for `None`
+	pub fn set_loop(&self, region: Option<(f64, f64)>) {
+		let (start, end) = match region {
+			Some((start, end)) => (
+				self.ms_to_sample_idx(start.min(end)),
+				self.ms_to_sample_idx(start.max(end)),
+			),
+			None => (0, 0),
+		};
+
+		self.loop_end.store(0, Ordering::SeqCst);
+		self.loop_start.store(start, Ordering::SeqCst);
+		self.loop_end.store(end, Ordering::SeqCst);
+	}
+
+	pub fn get_loop(&self) -> Option<(f64, f64)> {
+		let start = self.loop_start.load(Ordering::SeqCst);
+		let end = self.loop_end.load(Ordering::SeqCst);
+
+		(end > start).then(|| (self.sample_idx_to_ms(start), self.sample_idx_to_ms(end)))
+	}
+
+	/// Copies `length_ms` of audio starting at `ms`, faded in and out over `fade_ms`
+	fn preview_buffer(&self, ms: f64, length_ms: f64, fade_ms: f64) -> Option<SamplesBuffer<f32>> {
+		let samples = self.samples.as_ref()?;
+		let sample_rate = self.sample_rate.load(Ordering::SeqCst);
+		let channels = self.channels.load(Ordering::SeqCst);
+
+		let start = self.ms_to_sample_idx(ms);
+		let end = self.ms_to_sample_idx(ms + length_ms);
+		let frames = (end - start) / channels as usize;
+		if frames < 2 {
+			return None;
+		}
+
+		let fade = ((fade_ms / 1000. * sample_rate as f64) as usize).clamp(1, frames / 2);
+		let buffer: Vec<f32> = samples[start..end]
+			.chunks(channels as usize)
+			.enumerate()
+			.flat_map(|(i, frame)| {
+				let edge = i.min(frames - 1 - i);
+				let gain = if edge < fade {
+					0.5 - 0.5 * (PI * edge as f32 / fade as f32).cos()
+				} else {
+					1.
+				};
+				frame.iter().map(move |sample| sample * gain)
+			})
+			.collect();
+
+		Some(SamplesBuffer::new(channels, sample_rate, buffer))
+	}
+
+	/// Plays `length_ms` of audio from `ms` without moving the playhead,
+	/// cutting off any preview still playing
+	pub fn play_preview(&mut self, ms: f64, length_ms: f64) -> Result<()> {
+		let Some(buffer) = self.preview_buffer(ms, length_ms, 5.) else {
+			return Ok(());
+		};
+
+		let sink = Sink::try_new(&self.handle)?;
+		sink.set_volume(self.volume);
+		sink.append(buffer);
+
+		self.preview_sink = Some(sink);
+		self.preview = Some((ms, length_ms, Instant::now()));
+
+		Ok(())
+	}
+
+	/// Queues a short grain at `ms`, unless enough grains are already waiting
+	pub fn scrub(&mut self, ms: f64) -> Result<()> {
+		self.preview = None;
+
+		if self
+			.preview_sink
+			.as_ref()
+			.is_some_and(|sink| sink.len() >= SCRUB_QUEUED_GRAINS)
+		{
+			return Ok(());
+		}
+
+		let Some(buffer) = self.preview_buffer(ms, SCRUB_GRAIN_MS, SCRUB_GRAIN_MS / 2.) else {
+			return Ok(());
+		};
+
+		let sink = match self.preview_sink.take() {
+			Some(sink) => sink,
+			None => {
+				let sink = Sink::try_new(&self.handle)?;
+				sink.set_volume(self.volume);
+				sink
+			},
+		};
+		sink.append(buffer);
+		self.preview_sink = Some(sink);
+
+		Ok(())
+	}
+
+	/// Audible position of the preview started by [`Self::play_preview`], while it plays
+	pub fn get_preview_position_ms(&self) -> Option<f64> {
+		let (start, length, started) = self.preview?;
+		let elapsed = started.elapsed().as_secs_f64() * 1000. - self.output_latency_ms;
+
+		(elapsed < length).then(|| start + elapsed.max(0.))
 	}
 
 	pub fn set_volume(&mut self, volume: f32) {
@@ -238,6 +726,9 @@ impl AudioPlayer {
 		if let Some(sink) = &self.sink {
 			sink.set_volume(self.volume);
 		}
+		if let Some(sink) = &self.preview_sink {
+			sink.set_volume(self.volume);
+		}
 	}
 
 	pub fn get_volume(&self) -> f32 {
@@ -251,4 +742,12 @@ impl AudioPlayer {
 	pub fn get_metronome_volume(&self) -> f32 {
 		self.metronome_sink.volume()
 	}
+
+	pub fn set_metronome_subdivide(&self, subdivide: bool) {
+		self.metronome_subdivide.store(subdivide, Ordering::Relaxed);
+	}
+
+	pub fn get_metronome_subdivide(&self) -> bool {
+		self.metronome_subdivide.load(Ordering::Relaxed)
+	}
 }
@@ -0,0 +1,228 @@
+use std::env::current_dir;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::SystemTime;
+
+use crate::settings::SettingsManager;
+
+type CacheWriter = dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static;
+
+enum CacheCommand {
+	Write {
+		name: String,
+		writer: Box<CacheWriter>,
+	},
+	Clear,
+	/// Answered once every command before it is done
+	Flush(Sender<()>),
+}
+
+fn cache_entries(dir: &PathBuf) -> Vec<(PathBuf, u64, SystemTime)> {
+	let Ok(entries) = fs::read_dir(dir) else {
+		return vec![];
+	};
+
+	entries
+		.filter_map(|entry| {
+			let entry = entry.ok()?;
+			let metadata = entry.metadata().ok()?;
+			metadata.is_file().then(|| {
+				(
+					entry.path(),
+					metadata.len(),
+					metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+				)
+			})
+		})
+		.collect()
+}
+
+fn evict(dir: &PathBuf, limit: u64) -> u64 {
+	let mut entries = cache_entries(dir);
+	let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+
+	if size <= limit {
+		return size;
+	}
+
+	// Evict down to 90% of the limit so we don't have to rescan on every write
+	let target = limit / 10 * 9;
+
+	entries.sort_by_key(|(_, _, modified)| *modified);
+
+	for (path, len, _) in entries {
+		if size <= target {
+			break;
+		}
+
+		if fs::remove_file(path).is_ok() {
+			size -= len;
+		}
+	}
+
+	size
+}
+
+fn cache_writer_thread(
+	dir: PathBuf,
+	size: Arc<AtomicU64>,
+	limit: Arc<AtomicU64>,
+	rx: Receiver<CacheCommand>,
+) {
+	size.store(evict(&dir, limit.load(Ordering::SeqCst)), Ordering::SeqCst);
+
+	while let Ok(command) = rx.recv() {
+		match command {
+			CacheCommand::Write { name, writer } => {
+				let path = dir.join(name);
+				let tmp_path = path.with_extension("tmp");
+
+				let result = File::create(&tmp_path).and_then(|file| {
+					let mut file = BufWriter::new(file);
+					writer(&mut file)?;
+					file.flush()
+				});
+
+				// Entries are written under a temporary name first, so a
+				// partially written file is never picked up as valid
+				if result.is_ok() && fs::rename(&tmp_path, &path).is_ok() {
+					let len = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+					let total = size.fetch_add(len, Ordering::SeqCst) + len;
+
+					let limit = limit.load(Ordering::SeqCst);
+					if total > limit {
+						size.store(evict(&dir, limit), Ordering::SeqCst);
+					}
+				} else {
+					let _ = fs::remove_file(&tmp_path);
+				}
+			},
+			CacheCommand::Clear => {
+				for (path, _, _) in cache_entries(&dir) {
+					let _ = fs::remove_file(path);
+				}
+				size.store(0, Ordering::SeqCst);
+			},
+			CacheCommand::Flush(done) => {
+				let _ = done.send(());
+			},
+		}
+	}
+}
+
+/// On-disk cache of decoded audio and analysis results,
+/// keyed by the content hash of the audio file
+pub struct DiskCache {
+	dir: PathBuf,
+	size: Arc<AtomicU64>,
+	limit: Arc<AtomicU64>,
+	tx: Sender<CacheCommand>,
+}
+
+impl DiskCache {
+	pub fn new(settings: &SettingsManager) -> Self {
+		let dir = dirs::cache_dir()
+			.unwrap_or_else(|| current_dir().unwrap())
+			.join("spectral");
+
+		let _ = fs::create_dir_all(&dir);
+
+		let size = Arc::new(AtomicU64::new(0));
+		let limit = Arc::new(AtomicU64::new(
+			settings.read(|s| s.cache_limit_mb) * 1024 * 1024,
+		));
+
+		let (tx, rx) = mpsc::channel();
+
+		let _dir = dir.clone();
+		let _size = size.clone();
+		let _limit = limit.clone();
+		thread::spawn(move || {
+			cache_writer_thread(_dir, _size, _limit, rx);
+		});
+
+		Self {
+			dir,
+			size,
+			limit,
+			tx,
+		}
+	}
+
+	pub fn hash(bytes: &[u8]) -> u64 {
+		xxhash_rust::xxh3::xxh3_64(bytes)
+	}
+
+	pub fn open(&self, name: &str) -> Option<BufReader<File>> {
+		let path = self.dir.join(name);
+		let file = File::options().read(true).write(true).open(path).ok()?;
+
+		// Reads count as uses for eviction
+		let _ = file.set_modified(SystemTime::now());
+
+		Some(BufReader::new(file))
+	}
+
+	pub fn write(
+		&self,
+		name: String,
+		writer: impl FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+	) {
+		let _ = self.tx.send(CacheCommand::Write {
+			name,
+			writer: Box::new(writer),
+		});
+	}
+
+	pub fn clear(&self) {
+		let _ = self.tx.send(CacheCommand::Clear);
+	}
+
+	/// Blocks until every entry queued so far is written, for processes about to exit
+	pub fn flush(&self) {
+		let (tx, rx) = mpsc::channel();
+		if self.tx.send(CacheCommand::Flush(tx)).is_ok() {
+			let _ = rx.recv();
+		}
+	}
+
+	pub fn size(&self) -> u64 {
+		self.size.load(Ordering::SeqCst)
+	}
+
+	pub fn set_limit(&self, limit_mb: u64) {
+		self.limit.store(limit_mb * 1024 * 1024, Ordering::SeqCst);
+	}
+}
+
+pub fn read_f32s(reader: &mut impl Read, count: usize) -> io::Result<Vec<f32>> {
+	let mut values = Vec::with_capacity(count);
+	let mut chunk = vec![0u8; 64 * 1024];
+
+	while values.len() < count {
+		let len = ((count - values.len()) * 4).min(chunk.len());
+		reader.read_exact(&mut chunk[..len])?;
+
+		values.extend(
+			chunk[..len]
+				.chunks_exact(4)
+				.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+		);
+	}
+
+	Ok(values)
+}
+
+pub fn write_f32s(writer: &mut dyn Write, values: &[f32]) -> io::Result<()> {
+	for chunk in values.chunks(16 * 1024) {
+		let bytes: Vec<u8> = chunk.iter().flat_map(|v| v.to_le_bytes()).collect();
+		writer.write_all(&bytes)?;
+	}
+
+	Ok(())
+}
@@ -0,0 +1,142 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+
+use eyre::{OptionExt as _, Result, bail};
+use spectral::audio::{AudioData, ChannelSource};
+use spectral::cache::DiskCache;
+use spectral::export::read_csv_timing_points;
+use spectral::render::{RenderOptions, render_spectrogram_image};
+use spectral::settings::SettingsManager;
+use spectral::spectrogram::colormaps::ColormapLibrary;
+use spectral::spectrogram::colors::Palette;
+use spectral::spectrogram::hpss::SpectrogramView;
+use spectral::spectrogram::stats::SpectrumStats;
+
+const USAGE: &str = "usage: spectral render <audio> <output.png> [options]
+
+options:
+  --start <ms>          start of the rendered range (default: 0)
+  --end <ms>            end of the rendered range (default: end of audio)
+  --width <px>          image width (default: 1920)
+  --height <px>         image height (default: 1080)
+  --fft <size>          FFT size (default: 2048)
+  --channel <source>    mono, left, right, mid, side, lr or ms (default: mono)
+  --view <view>         full, percussive or harmonic (default: full)
+  --colormap <name>     built-in or custom colormap name (default: roseus)
+  --reverse             reverse the colormap
+  --gamma <value>       colormap gamma (default: 1)
+  --min-db <db>         bottom of the dB range (default: -80)
+  --max-db <db>         top of the dB range (default: 0)
+  --auto-db             pick the dB range from the track's statistics
+  --whiten              flatten the average spectrum of the track
+  --timing <csv>        timing points exported as CSV
+  --divisor <n>         beat snap divisor (default: 4)
+  --no-ticks            don't draw beat ticks
+  --no-timing-points    don't draw timing points
+  --no-axis             don't draw the frequency axis
+  --no-ruler            don't draw the time ruler";
+
+/// Renders a spectrogram image without opening a window
+pub fn render(args: &[String]) -> Result<()> {
+	let (Some(input), Some(output)) = (args.first(), args.get(1)) else {
+		bail!(USAGE);
+	};
+
+	let mut options = RenderOptions::new(0., f64::NAN, 1920, 1080);
+	let mut timing_points = vec![];
+
+	let mut colormap = None;
+	let mut reverse = false;
+	let mut gamma = 1.;
+	let mut auto_db = false;
+	let mut whiten = false;
+
+	let mut args = args[2..].iter();
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().ok_or_eyre(format!("missing value for {}", arg));
+
+		match arg.as_str() {
+			"--start" => options.start_ms = value()?.parse()?,
+			"--end" => options.end_ms = value()?.parse()?,
+			"--width" => options.width = value()?.parse()?,
+			"--height" => options.height = value()?.parse()?,
+			"--fft" => options.fft_size = value()?.parse()?,
+			"--channel" => options.channel_source = ChannelSource::from_str(value()?)?,
+			"--view" => options.view = SpectrogramView::from_str(value()?)?,
+			"--colormap" => colormap = Some(value()?.clone()),
+			"--reverse" => reverse = true,
+			"--gamma" => gamma = value()?.parse()?,
+			"--min-db" => options.min_db = value()?.parse()?,
+			"--max-db" => options.max_db = value()?.parse()?,
+			"--auto-db" => auto_db = true,
+			"--whiten" => whiten = true,
+			"--timing" => timing_points = read_csv_timing_points(&PathBuf::from(value()?))?,
+			"--divisor" => options.snap_divisor = value()?.parse()?,
+			"--no-ticks" => options.beat_ticks = false,
+			"--no-timing-points" => options.timing_points = false,
+			"--no-axis" => options.frequency_axis = false,
+			"--no-ruler" => options.time_ruler = false,
+			_ => bail!("unknown option: {}\n\n{}", arg, USAGE),
+		}
+	}
+
+	if !options.fft_size.is_power_of_two() || options.fft_size < 64 {
+		bail!("FFT size must be a power of two, at least 64");
+	}
+
+	let library = ColormapLibrary::load();
+	let colormap = match colormap {
+		Some(name) => library
+			.all()
+			.iter()
+			.find(|c| c.name().eq_ignore_ascii_case(&name))
+			.ok_or_eyre(format!(
+				"unknown colormap: {}, available: {}",
+				name,
+				library
+					.all()
+					.iter()
+					.map(|c| c.name())
+					.collect::<Vec<_>>()
+					.join(", ")
+			))?
+			.clone(),
+		None => library.all()[0].clone(),
+	};
+	options.palette = Palette::new(&colormap, reverse, gamma);
+
+	let settings = SettingsManager::new();
+	let cache = DiskCache::new(&settings);
+	let data = AudioData::load_from_file(input, &cache, |_| {}, &AtomicBool::new(false), |_| {})?;
+	// The process may exit before the cache gets to write the decoded audio
+	cache.flush();
+
+	if options.end_ms.is_nan() {
+		options.end_ms = data.duration;
+	}
+
+	if auto_db || whiten {
+		let stats = SpectrumStats::compute(
+			&data,
+			options.channel_source.analysis_channel(),
+			options.fft_size,
+		);
+
+		if whiten {
+			options.whitening = Some(stats.whitening.clone());
+		}
+
+		if auto_db && let Some((min_db, max_db)) = stats.auto_range(whiten) {
+			options.min_db = min_db;
+			options.max_db = max_db;
+		}
+	}
+
+	timing_points.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+
+	let image = render_spectrogram_image(&data, &timing_points, &options)?;
+	image.save_with_format(output, image::ImageFormat::Png)?;
+
+	Ok(())
+}
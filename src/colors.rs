@@ -15,8 +15,12 @@ pub const COLOR_TEXT_HIGHLIGHT: Color32 = Color32::from_rgb(50, 170, 255);
 pub const COLOR_AXES_STROKE: Color32 = Color32::from_gray(60);
 pub const COLOR_AXES_TEXT: Color32 = Color32::from_gray(120);
 
+pub const COLOR_RENDER_BACKGROUND: Color32 = Color32::from_gray(27);
+
 pub const COLOR_CURSOR: Color32 = Color32::from_gray(170);
 pub const COLOR_PLAYHEAD: Color32 = Color32::from_rgb(102, 255, 204);
+pub const COLOR_LOOP_REGION: Color32 = Color32::from_rgba_premultiplied(20, 50, 40, 50);
+pub const COLOR_LOOP_EDGE: Color32 = Color32::from_rgb(60, 160, 130);
 
 pub const COLOR_SCROLL: Color32 = Color32::from_gray(40);
 pub const COLOR_SCROLL_THUMB: Color32 = Color32::from_rgba_premultiplied(55, 55, 55, 55);
@@ -24,5 +28,14 @@ pub const COLOR_SCROLL_THUMB_HOVER: Color32 = Color32::from_rgba_premultiplied(6
 pub const COLOR_SCROLL_OUTLINE: Color32 = Color32::from_rgba_premultiplied(70, 70, 70, 70);
 pub const COLOR_SCROLL_OUTLINE_HOVER: Color32 = Color32::from_rgba_premultiplied(80, 80, 80, 80);
 
+pub const COLOR_WAVEFORM: Color32 = Color32::from_rgb(70, 130, 180);
+pub const COLOR_WAVEFORM_RMS: Color32 = Color32::from_rgb(140, 200, 240);
+
+pub const COLOR_ONSET: Color32 = Color32::from_rgba_premultiplied(200, 120, 40, 200);
+pub const COLOR_ONSET_PEAK: Color32 = Color32::from_rgb(255, 150, 50);
+
 pub const COLOR_TIMING_POINT_TEMPORARY: Color32 = Color32::CYAN;
 pub const COLOR_TIMING_POINT: Color32 = Color32::GOLD;
+pub const COLOR_TIMING_POINT_SELECTED: Color32 = Color32::from_rgb(255, 110, 50);
+pub const COLOR_BEAT_MARKER: Color32 = Color32::from_rgb(230, 90, 200);
+pub const COLOR_BOX_SELECT: Color32 = Color32::from_rgba_premultiplied(40, 60, 90, 60);
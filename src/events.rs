@@ -1,11 +1,37 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use eyre::Result;
 
 use crate::audio::AudioData;
+use crate::onset::OnsetEnvelope;
+use crate::spectrogram::stats::SpectrumStats;
+use crate::waveform::Waveform;
 
 pub enum SpectralEvent {
-	OpenAudio { path: PathBuf },
-	LoadAudio { data: Result<AudioData> },
-	Export { error: Option<String> },
+	OpenAudio {
+		path: PathBuf,
+	},
+	AudioProgress {
+		progress: f32,
+	},
+	LoadAudio {
+		data: Result<Arc<AudioData>>,
+	},
+	/// Audio sent early from the cache has been decoded
+	AudioDecoded {
+		data: Result<Arc<AudioData>>,
+	},
+	Waveform {
+		waveform: Waveform,
+	},
+	Onsets {
+		onsets: OnsetEnvelope,
+	},
+	SpectrumStats {
+		stats: SpectrumStats,
+	},
+	Export {
+		error: Option<String>,
+	},
 }
@@ -1,24 +1,130 @@
 use std::fs::File;
-use std::io::Write as _;
+use std::io::{self, Write};
 
-use eyre::Result;
+use eyre::{OptionExt as _, Result};
 
 use crate::timing::TimingPoint;
 
-pub fn create(mut file: File, timing_points: &[TimingPoint]) -> Result<()> {
-	writeln!(file, "offset,bpm,signature_numerator,signature_denominator")?;
+pub fn write(w: &mut impl Write, timing_points: &[TimingPoint]) -> io::Result<()> {
+	writeln!(
+		w,
+		"offset,bpm,signature_numerator,signature_denominator,grouping,swing,swing_unit,ramp"
+	)?;
 
 	for tp in timing_points {
 		writeln!(
-			file,
-			"{},{},{},{}",
-			tp.offset, tp.bpm, tp.signature.0, tp.signature.1,
+			w,
+			"{},{},{},{},{},{},{},{}",
+			tp.offset,
+			tp.bpm,
+			tp.signature.0,
+			tp.signature.1,
+			tp.grouping_text(),
+			tp.swing,
+			tp.swing_unit,
+			tp.ramp,
 		)?;
 	}
 
 	Ok(())
 }
 
+pub fn create(mut file: File, timing_points: &[TimingPoint]) -> Result<()> {
+	Ok(write(&mut file, timing_points)?)
+}
+
 pub fn patch(file: File, timing_points: &[TimingPoint]) -> Result<()> {
 	create(file, timing_points)
 }
+
+/// Reads timing points back from the format written by [`create`]
+pub fn parse(contents: &str) -> Result<Vec<TimingPoint>> {
+	contents
+		.lines()
+		.skip(1)
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| {
+			let mut fields = line.split(',').map(str::trim);
+			let mut next = || fields.next().ok_or_eyre("missing timing point field");
+
+			let mut tp = TimingPoint::new(next()?.parse()?, next()?.parse()?);
+			tp.signature = (next()?.parse()?, next()?.parse()?);
+
+			// Files written before groupings were stored use the default one
+			if let Some(grouping) = fields.next()
+				&& !tp.set_grouping_text(grouping)
+			{
+				eyre::bail!("invalid grouping: {}", grouping);
+			}
+
+			if let (Some(swing), Some(unit)) = (fields.next(), fields.next()) {
+				tp.swing = swing.parse::<f64>()?.clamp(0.5, 0.75);
+				tp.swing_unit = unit.parse()?;
+			}
+
+			if let Some(ramp) = fields.next() {
+				tp.ramp = ramp.parse()?;
+			}
+
+			Ok(tp)
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::timing::{SwingUnit, TempoRamp};
+
+	#[test]
+	fn round_trip() {
+		let mut ramping = TimingPoint::new(1234.5, 174.25);
+		ramping.ramp = TempoRamp::Exponential;
+
+		let mut swung = TimingPoint::new(5000., 96.);
+		swung.signature = (7, 8);
+		assert!(swung.set_grouping_text("3+2+2"));
+		swung.swing = 0.625;
+		swung.swing_unit = SwingUnit::Sixteenth;
+
+		let mut compound = TimingPoint::new(9000.25, 60000. / 333.);
+		compound.signature = (6, 8);
+
+		let timing_points = [ramping, swung, compound];
+		let mut written = vec![];
+		write(&mut written, &timing_points).unwrap();
+		let parsed = parse(&String::from_utf8(written).unwrap()).unwrap();
+
+		assert_eq!(parsed.len(), timing_points.len());
+		for (tp, parsed) in timing_points.iter().zip(&parsed) {
+			assert_eq!(parsed.offset, tp.offset);
+			assert_eq!(parsed.bpm, tp.bpm);
+			assert_eq!(parsed.signature, tp.signature);
+			assert_eq!(parsed.group_starts(), tp.group_starts());
+			assert_eq!(parsed.swing, tp.swing);
+			assert_eq!(parsed.swing_unit, tp.swing_unit);
+			assert_eq!(parsed.ramp, tp.ramp);
+		}
+	}
+
+	#[test]
+	fn parse_older_files() {
+		let parsed =
+			parse("offset,bpm,signature_numerator,signature_denominator\n100,120,3,4\n\n").unwrap();
+
+		assert_eq!(parsed.len(), 1);
+		assert_eq!(parsed[0].offset, 100.);
+		assert_eq!(parsed[0].signature, (3, 4));
+		assert!(!parsed[0].is_swung());
+		assert_eq!(parsed[0].ramp, TempoRamp::Constant);
+	}
+
+	#[test]
+	fn parse_errors() {
+		let header = "offset,bpm,signature_numerator,signature_denominator,grouping\n";
+
+		assert!(parse(&format!("{}100,120,4", header)).is_err());
+		assert!(parse(&format!("{}100,fast,4,4", header)).is_err());
+		assert!(parse(&format!("{}100,120,7,8,3+3", header)).is_err());
+	}
+}
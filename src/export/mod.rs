@@ -1,22 +1,27 @@
 use std::fmt::Display;
 use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
 use std::thread;
 
 use eyre::Result;
 use rfd::FileDialog;
 
+use crate::audio::AudioData;
 use crate::events::SpectralEvent;
+use crate::render::RenderOptions;
 use crate::timing::TimingPoint;
 
 mod csv;
 mod osu;
+mod png;
 
 trait ApplyExportFormat {
 	fn apply_format(self, fmt: ExportFormat) -> Self;
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ExportFormat {
 	Csv,
 	Osu,
@@ -49,24 +54,109 @@ impl ExportFormat {
 		&[ExportFormat::Osu]
 	}
 
-	fn create(self, file: File, timing_points: &[TimingPoint]) -> Result<()> {
+	pub fn all() -> &'static [Self] {
+		&[ExportFormat::Csv, ExportFormat::Osu]
+	}
+
+	/// Offset as it ends up in the exported file
+	pub fn written_offset(self, ms: f64) -> f64 {
+		match self {
+			Self::Csv => ms,
+			// Matches the `{:.0}` formatting used by the writer
+			Self::Osu => ms.round_ties_even(),
+		}
+	}
+
+	/// BPM of `tp` as read back from the exported file, in notes of its own
+	/// denominator
+	pub fn written_bpm(self, tp: &TimingPoint) -> f64 {
+		match self {
+			Self::Csv => tp.bpm,
+			Self::Osu => {
+				// Beat lengths are written with `{:.8}`
+				let (ms_per_beat, _) = osu::written_beat(tp);
+				format!("{:.8}", ms_per_beat)
+					.parse()
+					.map_or(tp.bpm, |written: f64| tp.bpm * ms_per_beat / written)
+			},
+		}
+	}
+
+	/// Signature as written, with the measure counted in quarter notes for formats
+	/// without denominators. `None` if it isn't a whole number of them.
+	pub fn written_signature(self, (beats, denominator): (u32, u32)) -> Option<(u32, u32)> {
+		if self.supports_denominator(denominator) {
+			Some((beats, denominator))
+		} else if (beats * 4) % denominator == 0 {
+			Some((beats * 4 / denominator, 4))
+		} else {
+			None
+		}
+	}
+
+	/// Formats without tempo ramps get them baked into constant steps
+	pub fn supports_ramps(self) -> bool {
+		match self {
+			Self::Csv => true,
+			Self::Osu => false,
+		}
+	}
+
+	pub fn supports_swing(self) -> bool {
+		match self {
+			Self::Csv => true,
+			Self::Osu => false,
+		}
+	}
+
+	pub fn supports_denominator(self, denominator: u32) -> bool {
+		match self {
+			Self::Csv => true,
+			// .osu files only store the numerator, in quarter notes
+			Self::Osu => denominator == 4,
+		}
+	}
+
+	fn create(self, file: File, timing_points: &[TimingPoint], ramp_step_beats: f64) -> Result<()> {
 		match self {
 			Self::Csv => csv::create(file, timing_points),
-			Self::Osu => osu::create(file, timing_points),
+			Self::Osu => osu::create(file, timing_points, ramp_step_beats),
 		}
 	}
 
-	fn patch(self, file: File, contents: String, timing_points: &[TimingPoint]) -> Result<()> {
+	fn patch(
+		self,
+		file: File,
+		contents: String,
+		timing_points: &[TimingPoint],
+		ramp_step_beats: f64,
+	) -> Result<()> {
 		match self {
 			Self::Csv => csv::patch(file, timing_points),
-			Self::Osu => osu::patch(file, contents, timing_points),
+			Self::Osu => osu::patch(file, contents, timing_points, ramp_step_beats),
 		}
 	}
 }
 
+pub fn read_csv_timing_points(path: &Path) -> Result<Vec<TimingPoint>> {
+	csv::parse(&fs::read_to_string(path)?)
+}
+
+/// Timing points in the CSV export format, as used for the clipboard
+pub fn timing_points_to_csv(timing_points: &[TimingPoint]) -> String {
+	let mut buffer = vec![];
+	let _ = csv::write(&mut buffer, timing_points);
+	String::from_utf8(buffer).unwrap_or_default()
+}
+
+pub fn timing_points_from_csv(contents: &str) -> Result<Vec<TimingPoint>> {
+	csv::parse(contents)
+}
+
 pub fn export_timing_points(
 	timing_points: Vec<TimingPoint>,
 	fmt: ExportFormat,
+	ramp_step_beats: f64,
 	tx: Sender<SpectralEvent>,
 ) {
 	thread::spawn(move || {
@@ -74,10 +164,10 @@ pub fn export_timing_points(
 			let result = if path.exists() {
 				let contents = fs::read_to_string(&path).unwrap();
 				let file = File::create(path).unwrap();
-				fmt.patch(file, contents, &timing_points)
+				fmt.patch(file, contents, &timing_points, ramp_step_beats)
 			} else {
 				let file = File::create_new(path).unwrap();
-				fmt.create(file, &timing_points)
+				fmt.create(file, &timing_points, ramp_step_beats)
 			};
 
 			let _ = tx.send(SpectralEvent::Export {
@@ -86,3 +176,23 @@ pub fn export_timing_points(
 		}
 	});
 }
+
+pub fn export_spectrogram_image(
+	data: Arc<AudioData>,
+	timing_points: Vec<TimingPoint>,
+	options: RenderOptions,
+	tx: Sender<SpectralEvent>,
+) {
+	thread::spawn(move || {
+		if let Some(path) = FileDialog::new()
+			.add_filter("PNG image", &["png"])
+			.save_file()
+		{
+			let result = png::create(&path, &data, &timing_points, &options);
+
+			let _ = tx.send(SpectralEvent::Export {
+				error: result.err().map(|e| e.to_string()),
+			});
+		}
+	});
+}
@@ -4,41 +4,68 @@ use std::io::Write as _;
 
 use eyre::Result;
 
-use crate::timing::TimingPoint;
+use crate::export::ExportFormat;
+use crate::timing::{TimingPoint, bake_ramps};
 
-pub fn create(mut file: File, timing_points: &[TimingPoint]) -> Result<()> {
-	writeln!(file, "osu file format v14")?;
-	writeln!(file)?;
-	writeln!(file, "[TimingPoints]")?;
+// Effect flag keeping a timing point from starting a new bar line
+const OMIT_FIRST_BARLINE: u32 = 8;
+
+/// Beat length and meter of a timing point in quarter notes, which is all the
+/// format counts. Measures that aren't a whole number of quarter notes keep the
+/// denominator's notes as beats, changing the tempo.
+pub(super) fn written_beat(tp: &TimingPoint) -> (f64, u32) {
+	match ExportFormat::Osu.written_signature(tp.signature) {
+		Some((beats, _)) => (tp.ms_per_beat() * tp.signature.1 as f64 / 4., beats),
+		None => (tp.ms_per_beat(), tp.signature.0),
+	}
+}
 
-	for tp in timing_points {
+/// Uninherited timing point lines, with tempo ramps baked into steps of
+/// `ramp_step_beats` beats since the format only has constant sections
+fn timing_point_lines(timing_points: &[TimingPoint], ramp_step_beats: f64) -> Result<String> {
+	let mut lines = String::new();
+
+	for (tp, in_measure) in bake_ramps(timing_points, ramp_step_beats) {
+		let (ms_per_beat, meter) = written_beat(&tp);
 		writeln!(
-			file,
-			"{:.0},{:.8},{},2,1,100,1,0",
+			lines,
+			"{:.0},{:.8},{},2,1,100,1,{}",
 			tp.offset,
-			tp.ms_per_beat(),
-			tp.signature.0
+			ms_per_beat,
+			meter,
+			if in_measure { OMIT_FIRST_BARLINE } else { 0 }
 		)?;
 	}
 
+	Ok(lines)
+}
+
+pub fn create(mut file: File, timing_points: &[TimingPoint], ramp_step_beats: f64) -> Result<()> {
+	writeln!(file, "osu file format v14")?;
+	writeln!(file)?;
+	writeln!(file, "[TimingPoints]")?;
+	write!(
+		file,
+		"{}",
+		timing_point_lines(timing_points, ramp_step_beats)?
+	)?;
+
 	Ok(())
 }
 
-pub fn patch(mut file: File, contents: String, timing_points: &[TimingPoint]) -> Result<()> {
+pub fn patch(
+	mut file: File,
+	contents: String,
+	timing_points: &[TimingPoint],
+	ramp_step_beats: f64,
+) -> Result<()> {
 	let mut in_timing = false;
 	let mut done = false;
 
-	let mut section = "[TimingPoints]\n".to_owned();
-
-	for tp in timing_points {
-		writeln!(
-			section,
-			"{:.0},{:.8},{},2,1,100,1,0",
-			tp.offset,
-			tp.ms_per_beat(),
-			tp.signature.0
-		)?;
-	}
+	let section = format!(
+		"[TimingPoints]\n{}",
+		timing_point_lines(timing_points, ramp_step_beats)?
+	);
 
 	for line in contents.lines() {
 		if line.trim() == "[TimingPoints]" {
@@ -67,3 +94,38 @@ pub fn patch(mut file: File, contents: String, timing_points: &[TimingPoint]) ->
 
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn signature(offset: f64, bpm: f64, signature: (u32, u32)) -> TimingPoint {
+		let mut tp = TimingPoint::new(offset, bpm);
+		tp.signature = signature;
+		tp
+	}
+
+	#[test]
+	fn compound_meter_is_written_in_quarter_notes() {
+		// Eighth notes 333 ms apart, so dotted quarters of 1000 ms
+		let lines = timing_point_lines(&[signature(100., 180., (6, 8))], 1.).unwrap();
+
+		assert_eq!(lines, "100,666.66666667,3,2,1,100,1,0\n");
+	}
+
+	#[test]
+	fn quarter_note_meters_are_unchanged() {
+		let lines = timing_point_lines(&[signature(0., 120., (7, 4))], 1.).unwrap();
+
+		assert_eq!(lines, "0,500.00000000,7,2,1,100,1,0\n");
+	}
+
+	#[test]
+	fn odd_eighth_meters_keep_eighth_note_beats() {
+		let tp = signature(0., 240., (7, 8));
+		let lines = timing_point_lines(&[tp], 1.).unwrap();
+
+		assert_eq!(lines, "0,250.00000000,7,2,1,100,1,0\n");
+		assert_eq!(ExportFormat::Osu.written_signature(tp.signature), None);
+	}
+}
@@ -0,0 +1,19 @@
+use std::path::Path;
+
+use eyre::Result;
+
+use crate::audio::AudioData;
+use crate::render::{RenderOptions, render_spectrogram_image};
+use crate::timing::TimingPoint;
+
+pub fn create(
+	path: &Path,
+	data: &AudioData,
+	timing_points: &[TimingPoint],
+	options: &RenderOptions,
+) -> Result<()> {
+	let image = render_spectrogram_image(data, timing_points, options)?;
+	image.save_with_format(path, image::ImageFormat::Png)?;
+
+	Ok(())
+}
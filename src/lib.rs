@@ -1,9 +1,15 @@
 pub mod audio;
+pub mod cache;
 pub mod colors;
 pub mod events;
+pub mod lint;
+pub mod onset;
+pub mod render;
 pub mod settings;
+pub mod tempo_map;
 pub mod timing;
 pub mod util;
+pub mod waveform;
 
 pub mod app;
 pub mod export;
@@ -0,0 +1,154 @@
+use crate::export::ExportFormat;
+use crate::timing::{TimingPoint, fractional_sections};
+
+// Sections ending closer than this to a whole beat are not reported
+pub const FRACTIONAL_TOLERANCE_MS: f64 = 1.;
+
+// Drift at the end of a section from the BPM being rounded on export
+const BPM_DRIFT_TOLERANCE_MS: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+	Warning,
+	Error,
+}
+
+/// A problem with the timing point at `index`, located at `ms` on the timeline
+pub struct Lint {
+	pub severity: Severity,
+	pub index: usize,
+	pub ms: f64,
+	pub message: String,
+}
+
+/// Checks sorted timing points for problems, `fmt` being the format they are going
+/// to be exported to. Errors come first, then everything in timeline order.
+pub fn lint_timing_points(
+	timing_points: &[TimingPoint],
+	duration: Option<f64>,
+	fmt: ExportFormat,
+) -> Vec<Lint> {
+	let mut lints = vec![];
+	let mut lint = |severity, index: usize, message| {
+		lints.push(Lint {
+			severity,
+			index,
+			ms: timing_points[index].offset,
+			message,
+		})
+	};
+
+	for (i, tp) in timing_points.iter().enumerate() {
+		if let Some(duration) = duration
+			&& tp.offset > duration
+		{
+			lint(
+				Severity::Error,
+				i,
+				"Starts after the end of the audio".into(),
+			);
+		}
+
+		let written = fmt.written_offset(tp.offset);
+		if (written - tp.offset).abs() > 0.01 {
+			lint(
+				Severity::Warning,
+				i,
+				format!("Offset {} will be written as {}", tp.offset, written),
+			);
+		}
+
+		let (beats, denominator) = tp.signature;
+		match fmt.written_signature(tp.signature) {
+			Some(written) if written == tp.signature => {},
+			Some((written_beats, written_denominator)) => lint(
+				Severity::Warning,
+				i,
+				format!(
+					"Signature {}/{} will be written as {}/{}",
+					beats, denominator, written_beats, written_denominator
+				),
+			),
+			None => lint(
+				Severity::Error,
+				i,
+				format!(
+					"Signature {}/{} can't be represented, it will be written as {}/4 at {} BPM instead of {}",
+					beats,
+					denominator,
+					beats,
+					tp.bpm,
+					tp.bpm * 4. / denominator as f64
+				),
+			),
+		}
+
+		if tp.is_swung() && !fmt.supports_swing() {
+			lint(
+				Severity::Warning,
+				i,
+				format!("{} has no swing, it will be exported straight", fmt),
+			);
+		}
+
+		let section_end = timing_points
+			.get(i + 1)
+			.map(|next| next.offset)
+			.or(duration);
+		let written_bpm = fmt.written_bpm(tp);
+		if let Some(end) = section_end
+			&& !tp.ramps_into(timing_points.get(i + 1))
+		{
+			let beats = (end - tp.offset).max(0.) / tp.ms_per_beat();
+			let drift = beats * (60000. / written_bpm - tp.ms_per_beat()).abs();
+
+			if drift > BPM_DRIFT_TOLERANCE_MS {
+				lint(
+					Severity::Warning,
+					i,
+					format!(
+						"BPM {} will be written as {}, drifting {:.2} ms by the end of the section",
+						tp.bpm, written_bpm, drift
+					),
+				);
+			}
+		}
+
+		if tp.ramps_into(timing_points.get(i + 1)) && !fmt.supports_ramps() {
+			lint(
+				Severity::Warning,
+				i,
+				format!("{} has no tempo ramps, it will be exported in steps", fmt),
+			);
+		}
+
+		let Some(next) = timing_points.get(i + 1) else {
+			continue;
+		};
+
+		if fmt.written_offset(next.offset) == written {
+			lint(Severity::Error, i, format!("Same offset as #{}", i + 2));
+		} else if tp.beats_at(next.offset, Some(next)) < 1. {
+			lint(
+				Severity::Warning,
+				i,
+				"Section is shorter than one beat".into(),
+			);
+		}
+	}
+
+	for (i, beats) in fractional_sections(timing_points, FRACTIONAL_TOLERANCE_MS) {
+		lint(
+			Severity::Warning,
+			i,
+			format!("Section ends on a fractional beat ({:.3} beats)", beats),
+		);
+	}
+
+	lints.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.ms.total_cmp(&b.ms)));
+	lints
+}
+
+pub fn has_errors(lints: &[Lint]) -> bool {
+	lints.iter().any(|lint| lint.severity == Severity::Error)
+}
@@ -1,9 +1,46 @@
-#![windows_subsystem = "windows"]
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+
+use std::env::args;
+use std::process::exit;
 
 use egui::IconData;
 use spectral::app::SpectralApp;
 
+mod cli;
+
+/// Release builds on Windows start without a console, so output of the command
+/// line goes to the console it was run from
+#[cfg(windows)]
+fn attach_console() {
+	const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+
+	#[link(name = "kernel32")]
+	unsafe extern "system" {
+		fn AttachConsole(process_id: u32) -> i32;
+	}
+
+	// Fails when started from Explorer, which has no console to attach to
+	unsafe {
+		AttachConsole(ATTACH_PARENT_PROCESS);
+	}
+}
+
 fn main() -> eframe::Result {
+	let args: Vec<String> = args().collect();
+
+	#[cfg(windows)]
+	if args.len() > 1 {
+		attach_console();
+	}
+
+	if args.get(1).is_some_and(|arg| arg == "render") {
+		if let Err(e) = cli::render(&args[2..]) {
+			eprintln!("{}", e);
+			exit(1);
+		}
+		return Ok(());
+	}
+
 	let (icon, w, h) = {
 		let bytes = include_bytes!("./assets/spectral_128.png");
 		let image = image::load_from_memory(bytes)
@@ -3,6 +3,7 @@ use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 
+use eyre::Result;
 use rodio::Sink;
 use rodio::buffer::SamplesBuffer;
 
@@ -15,12 +16,15 @@ mod samples;
 enum ClickType {
 	Downbeat,
 	Beat,
+	Pulse,
+	Subdivision,
 }
 
 fn check_metronome(
 	previous: f64,
 	current: f64,
 	timing_points: &[TimingPoint],
+	subdivide: bool,
 ) -> Option<ClickType> {
 	if timing_points.is_empty() {
 		return None;
@@ -31,21 +35,29 @@ fn check_metronome(
 		.rposition(|tp| tp.offset <= current)
 		.unwrap_or(0);
 
-	let tp = &timing_points[tp_idx];
-	let ms_per_beat = tp.ms_per_beat();
-
-	let current_beat = ((current - tp.offset) / ms_per_beat).floor() as i64;
-	let previous_beat = ((previous - tp.offset) / ms_per_beat).floor() as i64;
+	let (tp, next) = (&timing_points[tp_idx], timing_points.get(tp_idx + 1));
+	let per_beat = if subdivide {
+		tp.swing_unit.per_beat()
+	} else {
+		1
+	};
 
-	if current_beat > previous_beat && current >= tp.offset {
-		let ticks_per_measure = tp.signature.0 as i64;
+	// Clicks are counted on the straight grid so that subdivisions follow the swing
+	let tick = |ms: f64| (tp.unswing_beats(tp.beats_at(ms, next)) * per_beat as f64).floor() as i64;
+	let current_tick = tick(current);
 
-		let is_downbeat = current_beat % ticks_per_measure == 0;
+	if current_tick > tick(previous) && current >= tp.offset {
+		let current_beat = current_tick.div_euclid(per_beat);
+		let in_measure = current_beat.rem_euclid(tp.signature.0 as i64);
 
-		Some(if is_downbeat {
+		Some(if current_tick % per_beat != 0 {
+			ClickType::Subdivision
+		} else if in_measure == 0 {
 			ClickType::Downbeat
-		} else {
+		} else if tp.is_group_start(in_measure) {
 			ClickType::Beat
+		} else {
+			ClickType::Pulse
 		})
 	} else {
 		None
@@ -65,6 +77,8 @@ pub fn metronome_thread(
 		let previous_ms = playhead_ms;
 
 		if state.is_playing() {
+			// Clicks follow the read position rather than the audible one, since they
+			// go through the same output as the music and pick up the same latency
 			playhead_ms = state.get_position_ms();
 
 			if (playhead_ms - previous_ms).abs() >= 20. {
@@ -72,9 +86,12 @@ pub fn metronome_thread(
 				continue;
 			}
 
-			if let Some(click) =
-				check_metronome(previous_ms, playhead_ms, &timing_points.read().unwrap())
-			{
+			if let Some(click) = check_metronome(
+				previous_ms,
+				playhead_ms,
+				&timing_points.read().unwrap(),
+				state.subdivide(),
+			) {
 				let (samples, sample_rate, channels) = samples.get_sample(click);
 
 				let source = SamplesBuffer::new(channels, sample_rate, samples.as_ref().clone());
@@ -86,11 +103,37 @@ pub fn metronome_thread(
 	}
 }
 
+/// A click every `interval_ms` for `beats` beats, accenting every fourth one
+pub fn click_track(beats: usize, interval_ms: f64) -> Result<SamplesBuffer<f32>> {
+	let samples = MetronomeSamples::load()?;
+	let channels = samples.channels as usize;
+	let click_len = (interval_ms / 1000. * samples.sample_rate as f64) as usize * channels;
+
+	let mut track = vec![0.; click_len * beats];
+	for (beat, chunk) in track.chunks_mut(click_len).enumerate() {
+		let click = if beat % 4 == 0 {
+			&samples.downbeat
+		} else {
+			&samples.beat
+		};
+
+		let len = click.len().min(chunk.len());
+		chunk[..len].copy_from_slice(&click[..len]);
+	}
+
+	Ok(SamplesBuffer::new(
+		samples.channels,
+		samples.sample_rate,
+		track,
+	))
+}
+
 pub struct MetronomeState {
 	playing: Arc<AtomicBool>,
 	sample_rate: Arc<AtomicU32>,
 	channels: Arc<AtomicU16>,
 	position: Arc<AtomicUsize>,
+	subdivide: Arc<AtomicBool>,
 }
 
 impl From<&AudioPlayer> for MetronomeState {
@@ -100,6 +143,7 @@ impl From<&AudioPlayer> for MetronomeState {
 			sample_rate: value.sample_rate.clone(),
 			channels: value.channels.clone(),
 			position: value.position.clone(),
+			subdivide: value.metronome_subdivide.clone(),
 		}
 	}
 }
@@ -114,4 +158,8 @@ impl MetronomeState {
 	fn is_playing(&self) -> bool {
 		self.playing.load(Ordering::SeqCst)
 	}
+
+	fn subdivide(&self) -> bool {
+		self.subdivide.load(Ordering::Relaxed)
+	}
 }
@@ -9,9 +9,15 @@ use crate::metronome::ClickType;
 const METRONOME_DOWNBEAT: &[u8] = include_bytes!("../assets/metronome-tick-downbeat.wav");
 const METRONOME_BEAT: &[u8] = include_bytes!("../assets/metronome-tick.wav");
 
+// Beats inside a group and subdivisions use the beat click at these gains
+const PULSE_GAIN: f32 = 0.5;
+const SUBDIVISION_GAIN: f32 = 0.3;
+
 pub struct MetronomeSamples {
 	pub downbeat: Arc<Vec<f32>>,
 	pub beat: Arc<Vec<f32>>,
+	pub pulse: Arc<Vec<f32>>,
+	pub subdivision: Arc<Vec<f32>>,
 	pub sample_rate: u32,
 	pub channels: u16,
 }
@@ -20,10 +26,14 @@ impl MetronomeSamples {
 	pub fn load() -> Result<Self> {
 		let (downbeat, sample_rate, channels) = Self::decode(METRONOME_DOWNBEAT)?;
 		let (beat, _, _) = Self::decode(METRONOME_BEAT)?;
+		let pulse = beat.iter().map(|s| s * PULSE_GAIN).collect();
+		let subdivision = beat.iter().map(|s| s * SUBDIVISION_GAIN).collect();
 
 		Ok(Self {
 			downbeat: Arc::new(downbeat),
 			beat: Arc::new(beat),
+			pulse: Arc::new(pulse),
+			subdivision: Arc::new(subdivision),
 			sample_rate,
 			channels,
 		})
@@ -42,6 +52,8 @@ impl MetronomeSamples {
 		let samples = match click_type {
 			ClickType::Downbeat => self.downbeat.clone(),
 			ClickType::Beat => self.beat.clone(),
+			ClickType::Pulse => self.pulse.clone(),
+			ClickType::Subdivision => self.subdivision.clone(),
 		};
 		(samples, self.sample_rate, self.channels)
 	}
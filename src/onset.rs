@@ -0,0 +1,264 @@
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{AudioData, Channel};
+use crate::cache::{DiskCache, read_f32s, write_f32s};
+use crate::spectrogram::Spectrogram;
+use crate::spectrogram::hpss::SpectrogramView;
+
+const ONSET_HOP_MS: f64 = 5.;
+const ONSET_FLOOR_DB: f32 = -80.;
+
+const ONSET_CHUNK_FRAMES: usize = 2048;
+const ONSET_CACHE_MAGIC: &[u8; 4] = b"SPO1";
+
+// Peak picking windows, in frames
+const PEAK_MAX_RADIUS: usize = 6;
+const PEAK_MEAN_RADIUS: usize = 20;
+const PEAK_DELTA: f32 = 0.04;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OnsetBand {
+	Full,
+	Kick,
+	Snare,
+	HiHat,
+}
+
+impl OnsetBand {
+	pub fn all() -> &'static [Self] {
+		&[Self::Full, Self::Kick, Self::Snare, Self::HiHat]
+	}
+
+	pub fn range(self) -> (f32, f32) {
+		match self {
+			Self::Full => (0., f32::MAX),
+			Self::Kick => (30., 150.),
+			Self::Snare => (150., 4000.),
+			Self::HiHat => (5000., 16000.),
+		}
+	}
+}
+
+/// Spectral flux onset strength, normalized to 0..1, with detected peaks. Frames
+/// are the spectrogram's own, at a fixed hop.
+pub struct OnsetEnvelope {
+	pub hash: u64,
+	pub channel: Channel,
+	pub fft_size: usize,
+	pub band: OnsetBand,
+	/// Computed from the percussive part of the spectrum only
+	pub percussive: bool,
+	pub values: Vec<f32>,
+	pub peaks: Vec<f64>,
+	lead_ms: f64,
+}
+
+impl OnsetEnvelope {
+	pub fn compute(
+		data: &AudioData,
+		channel: Channel,
+		fft_size: usize,
+		band: OnsetBand,
+		percussive: bool,
+		cache: &DiskCache,
+	) -> Self {
+		let view = if percussive {
+			SpectrogramView::Percussive
+		} else {
+			SpectrogramView::Full
+		};
+
+		let cache_name = format!(
+			"{:016x}-{:?}-{:?}-{}-{:?}.onset",
+			data.hash, channel, view, fft_size, band
+		);
+
+		let values = match cache
+			.open(&cache_name)
+			.and_then(|mut reader| read_cached_values(&mut reader).ok())
+		{
+			Some(values) => values,
+			None => {
+				let values = Arc::new(spectral_flux(data, channel, fft_size, view, band));
+
+				let _values = values.clone();
+				cache.write(cache_name, move |w| {
+					w.write_all(ONSET_CACHE_MAGIC)?;
+					w.write_all(&(_values.len() as u64).to_le_bytes())?;
+					write_f32s(w, &_values)
+				});
+
+				Arc::unwrap_or_clone(values)
+			},
+		};
+
+		// Flux peaks once the onset enters the bulk of the window,
+		// roughly a quarter of the window before it reaches the center
+		let lead_ms = fft_size as f64 / 4. / data.sample_rate as f64 * 1000.;
+
+		let peaks = pick_peaks(&values)
+			.into_iter()
+			.map(|frame| frame_to_ms(frame) + lead_ms)
+			.collect();
+
+		Self {
+			hash: data.hash,
+			channel,
+			fft_size,
+			band,
+			percussive,
+			values,
+			peaks,
+			lead_ms,
+		}
+	}
+
+	pub fn hop_ms(&self) -> f64 {
+		ONSET_HOP_MS
+	}
+
+	/// Returns the strongest value between `start` and `end`
+	pub fn max_in(&self, start: f64, end: f64) -> Option<f32> {
+		let from = ms_to_frame(start - self.lead_ms).min(self.values.len());
+		let to = ms_to_frame(end - self.lead_ms)
+			.max(from + 1)
+			.min(self.values.len());
+
+		self.values[from..to].iter().copied().reduce(f32::max)
+	}
+
+	pub fn peaks_in(&self, start: f64, end: f64) -> &[f64] {
+		let from = self.peaks.partition_point(|&p| p < start);
+		let to = self.peaks.partition_point(|&p| p <= end);
+		&self.peaks[from..to]
+	}
+}
+
+fn frame_to_ms(frame: usize) -> f64 {
+	(frame as f64 + 0.5) * ONSET_HOP_MS
+}
+
+fn ms_to_frame(ms: f64) -> usize {
+	(ms / ONSET_HOP_MS).max(0.) as usize
+}
+
+fn read_cached_values(reader: &mut impl Read) -> io::Result<Vec<f32>> {
+	let mut header = [0u8; 12];
+	reader.read_exact(&mut header)?;
+
+	if &header[..4] != ONSET_CACHE_MAGIC {
+		return Err(io::ErrorKind::InvalidData.into());
+	}
+
+	let len = u64::from_le_bytes(header[4..].try_into().unwrap());
+	read_f32s(reader, len as usize)
+}
+
+fn spectral_flux(
+	data: &AudioData,
+	channel: Channel,
+	fft_size: usize,
+	view: SpectrogramView,
+	band: OnsetBand,
+) -> Vec<f32> {
+	let spectrogram = Spectrogram::new(fft_size);
+	let bins = spectrogram.bins();
+
+	let bin_hz = data.sample_rate as f32 / fft_size as f32;
+	let (lo, hi) = band.range();
+	let lo_bin = ((lo / bin_hz).floor() as usize).min(bins - 1);
+	let hi_bin = ((hi / bin_hz).ceil() as usize).clamp(lo_bin + 1, bins);
+
+	let frames = (data.duration / ONSET_HOP_MS).ceil() as usize;
+	let mut values = Vec::with_capacity(frames);
+
+	// Frames are computed in chunks, so the whole STFT never has to be kept in memory
+	let mut previous: Option<Vec<f32>> = None;
+	let mut chunk = vec![0.; ONSET_CHUNK_FRAMES * bins];
+
+	for chunk_start in (0..frames).step_by(ONSET_CHUNK_FRAMES) {
+		let len = ONSET_CHUNK_FRAMES.min(frames - chunk_start);
+		let start_time = chunk_start as f64 * ONSET_HOP_MS;
+		let columns = &mut chunk[..len * bins];
+
+		if view == SpectrogramView::Full {
+			spectrogram.compute_columns_into(data, channel, start_time, ONSET_HOP_MS, columns);
+		} else {
+			let separated = spectrogram.compute_view_columns(
+				data,
+				channel,
+				view,
+				start_time,
+				ONSET_HOP_MS,
+				len,
+			);
+			for (out, column) in columns.chunks_mut(bins).zip(separated) {
+				out.copy_from_slice(&column);
+			}
+		}
+
+		for column in columns.chunks(bins) {
+			let column: Vec<f32> = column[lo_bin..hi_bin]
+				.iter()
+				.map(|&db| db.max(ONSET_FLOOR_DB))
+				.collect();
+
+			let flux = match &previous {
+				Some(prev) => {
+					column
+						.iter()
+						.zip(prev)
+						.map(|(cur, prev)| (cur - prev).max(0.))
+						.sum::<f32>() / column.len() as f32
+				},
+				None => 0.,
+			};
+
+			values.push(flux);
+			previous = Some(column);
+		}
+	}
+
+	let max = values.iter().copied().fold(0., f32::max);
+	if max > 0. {
+		values.iter_mut().for_each(|v| *v /= max);
+	}
+
+	values
+}
+
+fn pick_peaks(values: &[f32]) -> Vec<usize> {
+	let mut peaks: Vec<usize> = vec![];
+
+	for (i, &value) in values.iter().enumerate() {
+		let max_from = i.saturating_sub(PEAK_MAX_RADIUS);
+		let max_to = (i + PEAK_MAX_RADIUS + 1).min(values.len());
+		if values[max_from..max_to].iter().any(|&v| v > value) {
+			continue;
+		}
+
+		let mean_from = i.saturating_sub(PEAK_MEAN_RADIUS);
+		let mean_to = (i + PEAK_MEAN_RADIUS + 1).min(values.len());
+		let window = &values[mean_from..mean_to];
+		let mean = window.iter().sum::<f32>() / window.len() as f32;
+
+		if value < mean + PEAK_DELTA {
+			continue;
+		}
+
+		// Plateaus would otherwise produce several adjacent peaks
+		if peaks
+			.last()
+			.is_some_and(|&last| i - last <= PEAK_MAX_RADIUS)
+		{
+			continue;
+		}
+
+		peaks.push(i);
+	}
+
+	peaks
+}
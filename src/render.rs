@@ -0,0 +1,405 @@
+use egui::Color32;
+use eyre::{Result, ensure};
+use image::{Rgba, RgbaImage};
+
+use crate::audio::{AudioData, ChannelSource};
+use crate::colors::{
+	COLOR_AXES_STROKE, COLOR_AXES_TEXT, COLOR_RENDER_BACKGROUND, COLOR_TIMING_POINT,
+};
+use crate::spectrogram::colors::{Colormap, Palette};
+use crate::spectrogram::hpss::SpectrogramView;
+use crate::spectrogram::stats::Whitening;
+use crate::spectrogram::{Spectrogram, normalize_db};
+use crate::timing::{TimingPoint, beat_ticks};
+use crate::util::format_time;
+
+const FREQUENCY_AXIS_WIDTH: u32 = 40;
+const TIME_RULER_HEIGHT: u32 = 20;
+
+const GLYPH_WIDTH: i64 = 3;
+const GLYPH_HEIGHT: i64 = 5;
+const GLYPH_SCALE: i64 = 2;
+
+/// Everything needed to render a time range of the spectrogram, independent of the UI
+#[derive(Clone)]
+pub struct RenderOptions {
+	pub start_ms: f64,
+	pub end_ms: f64,
+	/// Output image size, including the axes
+	pub width: u32,
+	pub height: u32,
+
+	pub fft_size: usize,
+	pub channel_source: ChannelSource,
+	pub view: SpectrogramView,
+	pub palette: Palette,
+	pub min_db: f32,
+	pub max_db: f32,
+	pub whitening: Option<Whitening>,
+
+	pub snap_divisor: i64,
+	pub beat_ticks: bool,
+	pub timing_points: bool,
+	pub frequency_axis: bool,
+	pub time_ruler: bool,
+}
+
+impl RenderOptions {
+	pub fn new(start_ms: f64, end_ms: f64, width: u32, height: u32) -> Self {
+		Self {
+			start_ms,
+			end_ms,
+			width,
+			height,
+
+			fft_size: 2048,
+			channel_source: ChannelSource::Mono,
+			view: SpectrogramView::Full,
+			palette: Palette::new(&Colormap::builtin()[0], false, 1.),
+			min_db: -80.,
+			max_db: 0.,
+			whitening: None,
+
+			snap_divisor: 4,
+			beat_ticks: true,
+			timing_points: true,
+			frequency_axis: true,
+			time_ruler: true,
+		}
+	}
+
+	fn ms_to_x(&self, ms: f64, left: u32, width: u32) -> f64 {
+		left as f64 + (ms - self.start_ms) / (self.end_ms - self.start_ms) * width as f64
+	}
+}
+
+pub fn render_spectrogram_image(
+	data: &AudioData,
+	timing_points: &[TimingPoint],
+	options: &RenderOptions,
+) -> Result<RgbaImage> {
+	ensure!(
+		options.end_ms > options.start_ms,
+		"end of the range must be after its start"
+	);
+
+	let left = if options.frequency_axis {
+		FREQUENCY_AXIS_WIDTH
+	} else {
+		0
+	};
+	let top = if options.time_ruler {
+		TIME_RULER_HEIGHT
+	} else {
+		0
+	};
+
+	ensure!(
+		options.width > left && options.height > top,
+		"image is too small to fit the axes"
+	);
+
+	let width = options.width - left;
+	let height = options.height - top;
+
+	let mut canvas = Canvas::new(options.width, options.height);
+
+	let spectrogram = Spectrogram::new(options.fft_size);
+	let channels = options.channel_source.channels();
+	let rows: Vec<_> = (0..channels.len() as u32)
+		.map(|i| {
+			(
+				top + i * height / channels.len() as u32,
+				top + (i + 1) * height / channels.len() as u32,
+			)
+		})
+		.collect();
+
+	let ms_per_column = (options.end_ms - options.start_ms) / width as f64;
+
+	for (&channel, &(row_top, row_bottom)) in channels.iter().zip(&rows) {
+		let columns = spectrogram.compute_view_columns(
+			data,
+			channel,
+			options.view,
+			options.start_ms,
+			ms_per_column,
+			width as usize,
+		);
+		let row_height = (row_bottom - row_top) as usize;
+
+		for (x, column) in columns.iter().enumerate() {
+			let center = options.start_ms + (x as f64 + 0.5) * ms_per_column;
+			if center < 0. || center > data.duration {
+				continue;
+			}
+
+			let bins = column.len();
+
+			for y in 0..row_height {
+				// Take the loudest bin under the pixel so narrow peaks survive downscaling
+				let lo = (row_height - y - 1) * bins / row_height;
+				let hi = ((row_height - y) * bins / row_height).max(lo + 1);
+				let db = (lo..hi)
+					.map(|bin| {
+						let db = column[bin];
+						options.whitening.as_ref().map_or(db, |w| w.apply(db, bin))
+					})
+					.fold(f32::MIN, f32::max);
+
+				let color =
+					options
+						.palette
+						.get_color(normalize_db(db, options.min_db, options.max_db));
+
+				canvas.put(left as i64 + x as i64, row_top as i64 + y as i64, color);
+			}
+		}
+	}
+
+	for &(row_top, _) in rows.iter().skip(1) {
+		canvas.fill_rect(
+			left as i64,
+			row_top as i64,
+			options.width as i64,
+			row_top as i64 + 1,
+			COLOR_AXES_STROKE,
+		);
+	}
+
+	if options.beat_ticks {
+		let ticks = beat_ticks(
+			timing_points,
+			options.snap_divisor,
+			options.start_ms,
+			options.end_ms,
+			data.duration,
+		);
+
+		for (tick_ms, snap) in ticks {
+			let x = options.ms_to_x(tick_ms, left, width);
+			let tick_height = (snap.height() * height as f32).round() as i64;
+
+			canvas.vline(
+				x,
+				options.height as i64 - tick_height,
+				options.height as i64,
+				snap.width(),
+				snap.color(),
+			);
+		}
+	}
+
+	if options.timing_points {
+		for tp in timing_points
+			.iter()
+			.filter(|tp| tp.offset >= options.start_ms && tp.offset <= options.end_ms)
+		{
+			let x = options.ms_to_x(tp.offset, left, width);
+
+			canvas.vline(x, top as i64, options.height as i64, 2., COLOR_TIMING_POINT);
+			canvas.triangle_down(x, top as i64, 8., 12, COLOR_TIMING_POINT);
+		}
+	}
+
+	if options.frequency_axis {
+		canvas.fill_rect(
+			0,
+			0,
+			left as i64,
+			options.height as i64,
+			COLOR_RENDER_BACKGROUND,
+		);
+
+		let max_freq = data.sample_rate as f32 / 2.;
+
+		for &(row_top, row_bottom) in &rows {
+			let row_height = (row_bottom - row_top) as f32;
+
+			// Stacked rows only get every other label
+			let step = if row_height < 250. { 2 } else { 1 };
+
+			let freqs = [
+				2000., 4000., 6000., 8000., 10000., 12000., 14000., 16000., 18000., 20000.,
+			]
+			.into_iter()
+			.skip(step - 1)
+			.step_by(step)
+			.filter(|&f| f <= max_freq);
+
+			for freq in freqs {
+				let y = (row_bottom as f32 - (freq / max_freq) * row_height).round() as i64;
+
+				canvas.fill_rect(left as i64 - 4, y, left as i64, y + 1, COLOR_AXES_STROKE);
+
+				let label = if freq >= 1000. {
+					format!("{}k", (freq / 1000.) as u16)
+				} else {
+					format!("{}", freq as u16)
+				};
+
+				canvas.text(
+					(left as i64 - text_width(&label)) / 2,
+					y - GLYPH_HEIGHT * GLYPH_SCALE / 2,
+					&label,
+					COLOR_AXES_TEXT,
+				);
+			}
+		}
+	}
+
+	if options.time_ruler {
+		canvas.fill_rect(
+			0,
+			0,
+			options.width as i64,
+			top as i64,
+			COLOR_RENDER_BACKGROUND,
+		);
+
+		let duration = options.end_ms - options.start_ms;
+		let target_ticks = (width / 100) as f64;
+
+		let interval = [
+			100., 200., 500., 1000., 2000., 5000., 10000., 15000., 30000., 60000.,
+		]
+		.iter()
+		.find(|&&i| duration / i < target_ticks * 2.0)
+		.copied()
+		.unwrap_or(60000.);
+
+		let start_tick = (options.start_ms / interval).ceil() as i64;
+		let end_tick = (options.end_ms / interval).floor() as i64;
+
+		for tick in start_tick..=end_tick {
+			let ms = tick as f64 * interval;
+			let x = options.ms_to_x(ms, left, width).round() as i64;
+
+			canvas.fill_rect(x, 0, x + 1, top as i64, COLOR_AXES_STROKE);
+			canvas.text(
+				x + 3,
+				(top as i64 - GLYPH_HEIGHT * GLYPH_SCALE) / 2,
+				&format_time(ms),
+				COLOR_AXES_TEXT,
+			);
+		}
+	}
+
+	Ok(canvas.image)
+}
+
+struct Canvas {
+	image: RgbaImage,
+}
+
+impl Canvas {
+	fn new(width: u32, height: u32) -> Self {
+		let [r, g, b, a] = COLOR_RENDER_BACKGROUND.to_array();
+
+		Self {
+			image: RgbaImage::from_pixel(width, height, Rgba([r, g, b, a])),
+		}
+	}
+
+	fn contains(&self, x: i64, y: i64) -> bool {
+		x >= 0 && y >= 0 && x < self.image.width() as i64 && y < self.image.height() as i64
+	}
+
+	fn put(&mut self, x: i64, y: i64, color: Color32) {
+		if self.contains(x, y) {
+			self.image
+				.put_pixel(x as u32, y as u32, Rgba(color.to_array()));
+		}
+	}
+
+	/// Blends a premultiplied color over the pixel
+	fn blend(&mut self, x: i64, y: i64, color: Color32) {
+		if !self.contains(x, y) {
+			return;
+		}
+
+		let src = color.to_array();
+		let inverse = 255 - src[3] as u32;
+
+		let pixel = self.image.get_pixel_mut(x as u32, y as u32);
+		for (dst, src) in pixel.0.iter_mut().zip(src) {
+			*dst = (src as u32 + *dst as u32 * inverse / 255).min(255) as u8;
+		}
+	}
+
+	fn fill_rect(&mut self, x0: i64, y0: i64, x1: i64, y1: i64, color: Color32) {
+		for y in y0.max(0)..y1.min(self.image.height() as i64) {
+			for x in x0.max(0)..x1.min(self.image.width() as i64) {
+				self.blend(x, y, color);
+			}
+		}
+	}
+
+	fn vline(&mut self, x: f64, y0: i64, y1: i64, width: f32, color: Color32) {
+		let half = width as f64 / 2.;
+		let left = (x - half).round() as i64;
+		let right = ((x + half).round() as i64).max(left + 1);
+
+		self.fill_rect(left, y0, right, y1, color);
+	}
+
+	fn triangle_down(&mut self, x: f64, top: i64, half_width: f64, height: i64, color: Color32) {
+		for dy in 0..height {
+			let w = half_width * (1. - dy as f64 / height as f64);
+			self.fill_rect(
+				(x - w).round() as i64,
+				top + dy,
+				((x + w).round() as i64).max((x - w).round() as i64 + 1),
+				top + dy + 1,
+				color,
+			);
+		}
+	}
+
+	fn text(&mut self, x: i64, y: i64, text: &str, color: Color32) {
+		for (i, c) in text.chars().enumerate() {
+			let Some(rows) = glyph(c) else {
+				continue;
+			};
+
+			let glyph_x = x + i as i64 * (GLYPH_WIDTH + 1) * GLYPH_SCALE;
+
+			for (row, bits) in rows.iter().enumerate() {
+				for col in 0..GLYPH_WIDTH {
+					if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+						continue;
+					}
+
+					let px = glyph_x + col * GLYPH_SCALE;
+					let py = y + row as i64 * GLYPH_SCALE;
+					self.fill_rect(px, py, px + GLYPH_SCALE, py + GLYPH_SCALE, color);
+				}
+			}
+		}
+	}
+}
+
+fn text_width(text: &str) -> i64 {
+	let count = text.chars().count() as i64;
+	(count * (GLYPH_WIDTH + 1) - 1).max(0) * GLYPH_SCALE
+}
+
+// Tiny 3x5 bitmap font covering the characters used by axis labels
+fn glyph(c: char) -> Option<[u8; GLYPH_HEIGHT as usize]> {
+	Some(match c {
+		'0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+		'1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+		'2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+		'3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+		'4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+		'5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+		'6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+		'7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+		'8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+		'9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+		':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+		'.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+		'k' => [0b100, 0b101, 0b110, 0b101, 0b101],
+		_ => return None,
+	})
+}
@@ -7,7 +7,19 @@ use std::{fs, thread};
 
 use serde::{Deserialize, Serialize};
 
-use crate::spectrogram::colors::Colormap;
+use crate::audio::ChannelSource;
+use crate::onset::OnsetBand;
+use crate::spectrogram::hpss::SpectrogramView;
+use crate::spectrogram::stats::DbRangeMode;
+use crate::timing::ResnapMode;
+use crate::waveform::WaveformLane;
+
+/// Directory holding the settings file and user-provided assets
+pub fn config_dir() -> PathBuf {
+	dirs::config_local_dir()
+		.unwrap_or_else(|| current_dir().unwrap())
+		.join("spectral")
+}
 
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
@@ -17,8 +29,32 @@ pub struct Settings {
 
 	pub audio_volume: f32,
 	pub metronome_volume: f32,
+	pub metronome_subdivide: bool,
+	pub output_latency_ms: f64,
+	pub preview_length_ms: f64,
+
+	pub colormap: String,
+	pub colormap_reverse: bool,
+	pub colormap_gamma: f32,
+
+	pub db_range_mode: DbRangeMode,
+	pub whitening: bool,
+	pub channel_source: ChannelSource,
+	pub spectrogram_view: SpectrogramView,
+
+	pub waveform_lane: WaveformLane,
+	pub waveform_rms: bool,
+
+	pub show_onsets: bool,
+	pub onset_band: OnsetBand,
+	pub onsets_percussive: bool,
+	pub snap_to_onsets: bool,
+
+	pub resnap_mode: ResnapMode,
 
-	pub colormap: Colormap,
+	pub cache_limit_mb: u64,
+	pub history_capacity: usize,
+	pub ramp_step_beats: f64,
 }
 
 impl Default for Settings {
@@ -28,17 +64,39 @@ impl Default for Settings {
 
 			audio_volume: 0.4,
 			metronome_volume: 0.2,
+			metronome_subdivide: false,
+			output_latency_ms: 0.,
+			preview_length_ms: 400.,
+
+			colormap: "Roseus".into(),
+			colormap_reverse: false,
+			colormap_gamma: 1.,
+
+			db_range_mode: DbRangeMode::Manual,
+			whitening: false,
+			channel_source: ChannelSource::Mono,
+			spectrogram_view: SpectrogramView::Full,
+
+			waveform_lane: WaveformLane::Off,
+			waveform_rms: true,
+
+			show_onsets: false,
+			onset_band: OnsetBand::Full,
+			onsets_percussive: false,
+			snap_to_onsets: true,
+
+			resnap_mode: ResnapMode::Off,
 
-			colormap: Colormap::Roseus,
+			cache_limit_mb: 1024,
+			history_capacity: 200,
+			ramp_step_beats: 1.,
 		}
 	}
 }
 
 impl Settings {
 	fn load() -> Self {
-		let _save_path = dirs::config_local_dir()
-			.unwrap_or_else(|| current_dir().unwrap())
-			.join("spectral");
+		let _save_path = config_dir();
 
 		if !_save_path.exists() {
 			fs::create_dir_all(&_save_path).expect("unable to create config directory");
@@ -0,0 +1,191 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{OptionExt as _, Result, bail, ensure};
+use serde::Deserialize;
+
+use crate::settings::config_dir;
+use crate::spectrogram::colors::{ColorTuple, Colormap};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonColormap {
+	Stops(Vec<JsonStop>),
+	Object { colors: Vec<JsonStop> },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonStop {
+	Rgb(Vec<f32>),
+	Hex(String),
+}
+
+/// Built-in colormaps followed by the ones loaded from the `colormaps` folder
+pub struct ColormapLibrary {
+	colormaps: Vec<Colormap>,
+	builtin_count: usize,
+	errors: Vec<String>,
+}
+
+impl ColormapLibrary {
+	pub fn dir() -> PathBuf {
+		config_dir().join("colormaps")
+	}
+
+	pub fn load() -> Self {
+		let mut colormaps = Colormap::builtin();
+		let builtin_count = colormaps.len();
+		let mut errors = vec![];
+
+		let mut paths: Vec<_> = fs::read_dir(Self::dir())
+			.into_iter()
+			.flatten()
+			.filter_map(|entry| entry.ok().map(|e| e.path()))
+			.filter(|path| path.is_file())
+			.collect();
+		paths.sort();
+
+		for path in paths {
+			let Some(name) = path.file_stem().map(|s| s.to_string_lossy().into_owned()) else {
+				continue;
+			};
+
+			let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+			if colormaps.iter().any(|c| c.name() == name) {
+				errors.push(format!("{}: name \"{}\" is already taken", file_name, name));
+				continue;
+			}
+
+			match read_stops(&path) {
+				Ok(stops) => colormaps.push(Colormap::new(name, &stops)),
+				Err(e) => errors.push(format!("{}: {}", file_name, e)),
+			}
+		}
+
+		Self {
+			colormaps,
+			builtin_count,
+			errors,
+		}
+	}
+
+	pub fn all(&self) -> &[Colormap] {
+		&self.colormaps
+	}
+
+	pub fn builtin(&self) -> &[Colormap] {
+		&self.colormaps[..self.builtin_count]
+	}
+
+	pub fn custom(&self) -> &[Colormap] {
+		&self.colormaps[self.builtin_count..]
+	}
+
+	/// Problems with files that couldn't be loaded
+	pub fn errors(&self) -> &[String] {
+		&self.errors
+	}
+
+	pub fn get(&self, name: &str) -> Option<&Colormap> {
+		self.colormaps.iter().find(|c| c.name() == name)
+	}
+
+	/// Falls back to the first built-in, e.g. when a custom colormap file was removed
+	pub fn get_or_default(&self, name: &str) -> Colormap {
+		self.get(name).unwrap_or(&self.colormaps[0]).clone()
+	}
+}
+
+fn read_stops(path: &Path) -> Result<Vec<ColorTuple>> {
+	let extension = path
+		.extension()
+		.map(|e| e.to_string_lossy().to_lowercase())
+		.unwrap_or_default();
+
+	let stops = match extension.as_str() {
+		"csv" => parse_csv(&fs::read_to_string(path)?)?,
+		"json" => parse_json(&fs::read_to_string(path)?)?,
+		"png" => read_png(path)?,
+		_ => bail!("unsupported file type, expected .csv, .json or .png"),
+	};
+
+	ensure!(stops.len() >= 2, "colormap needs at least 2 colors");
+
+	Ok(stops)
+}
+
+/// Stops may be given either in 0..1 or 0..255, the latter is detected by any value above 1
+fn normalize_stops(stops: Vec<ColorTuple>) -> Vec<ColorTuple> {
+	if stops.iter().any(|&(r, g, b)| r > 1. || g > 1. || b > 1.) {
+		stops
+			.into_iter()
+			.map(|(r, g, b)| (r / 255., g / 255., b / 255.))
+			.collect()
+	} else {
+		stops
+	}
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<ColorTuple>> {
+	let mut stops = vec![];
+
+	for (i, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		let values: Result<Vec<f32>, _> = line.split(',').map(|v| v.trim().parse()).collect();
+
+		match values {
+			Ok(values) if values.len() >= 3 => stops.push((values[0], values[1], values[2])),
+			// The first line may be a header
+			Err(_) if stops.is_empty() && i == 0 => continue,
+			_ => bail!("line {}: expected r,g,b", i + 1),
+		}
+	}
+
+	Ok(normalize_stops(stops))
+}
+
+fn parse_json(contents: &str) -> Result<Vec<ColorTuple>> {
+	let stops = match serde_json::from_str(contents)? {
+		JsonColormap::Stops(stops) | JsonColormap::Object { colors: stops } => stops,
+	};
+
+	let stops = stops
+		.into_iter()
+		.map(|stop| match stop {
+			JsonStop::Rgb(values) if values.len() >= 3 => Ok((values[0], values[1], values[2])),
+			JsonStop::Rgb(_) => bail!("expected [r, g, b]"),
+			JsonStop::Hex(hex) => parse_hex(&hex),
+		})
+		.collect::<Result<Vec<_>>>()?;
+
+	Ok(normalize_stops(stops))
+}
+
+fn parse_hex(hex: &str) -> Result<ColorTuple> {
+	let hex = hex.trim_start_matches('#');
+	ensure!(hex.len() == 6, "expected #rrggbb, got \"{}\"", hex);
+
+	let channel =
+		|i: usize| -> Result<f32> { Ok(u8::from_str_radix(&hex[i..i + 2], 16)? as f32 / 255.) };
+
+	Ok((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Reads a horizontal gradient, sampling the middle row
+fn read_png(path: &Path) -> Result<Vec<ColorTuple>> {
+	let image = image::open(path)?.into_rgb8();
+	let y = image.height().checked_sub(1).ok_or_eyre("image is empty")? / 2;
+
+	Ok((0..image.width())
+		.map(|x| {
+			let [r, g, b] = image.get_pixel(x, y).0;
+			(r as f32 / 255., g as f32 / 255., b as f32 / 255.)
+		})
+		.collect())
+}
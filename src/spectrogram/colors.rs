@@ -22,10 +22,15 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
 use egui::Color32;
-use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+pub type ColorTuple = (f32, f32, f32);
 
-type ColorTuple = (f32, f32, f32);
+pub const COLORMAP_SIZE: usize = 256;
 
 const ROSEUS_DATA: [ColorTuple; 256] = [
 	(0.004528, 0.004341, 0.004307),
@@ -804,55 +809,130 @@ const LAVENDULA_DATA: [ColorTuple; 256] = [
 	(0.997031, 0.974334, 0.968899),
 ];
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
-pub enum Colormap {
-	Roseus,
-	Arcus,
-	Lavendula,
-	Magma,
+/// A named gradient, resampled to [`COLORMAP_SIZE`] evenly spaced colors
+#[derive(Clone)]
+pub struct Colormap {
+	name: String,
+	colors: Arc<[ColorTuple]>,
 }
 
 impl Colormap {
-	pub fn get_color(self, t: f32) -> Color32 {
-		match self {
-			Self::Roseus => get_map_color(&ROSEUS_DATA, t),
-			Self::Arcus => get_map_color(&ARCUS_DATA, t),
-			Self::Lavendula => get_map_color(&LAVENDULA_DATA, t),
-			Self::Magma => {
-				let t = t.clamp(0.0, 1.0);
+	/// Builds a colormap from evenly spaced stops, interpolating between them
+	pub fn new(name: impl Into<String>, stops: &[ColorTuple]) -> Self {
+		let colors = (0..COLORMAP_SIZE)
+			.map(|i| {
+				if stops.len() == 1 {
+					return stops[0];
+				}
 
-				let (r, g, b) = if t < 0.2 {
-					let s = t / 0.2;
-					(0.001 + s * 0.131, 0.0 + s * 0.025, 0.014 + s * 0.227)
-				} else if t < 0.4 {
-					let s = (t - 0.2) / 0.2;
-					(0.132 + s * 0.347, 0.025 + s * 0.041, 0.241 + s * 0.224)
-				} else if t < 0.6 {
-					let s = (t - 0.4) / 0.2;
-					(0.479 + s * 0.286, 0.066 + s * 0.159, 0.465 + s * 0.004)
-				} else if t < 0.8 {
-					let s = (t - 0.6) / 0.2;
-					(0.765 + s * 0.180, 0.225 + s * 0.339, 0.469 - s * 0.169)
-				} else {
-					let s = (t - 0.8) / 0.2;
-					(0.945 + s * 0.046, 0.564 + s * 0.360, 0.300 + s * 0.463)
-				};
+				let t = i as f32 / (COLORMAP_SIZE - 1) as f32 * (stops.len() - 1) as f32;
+				let lo = (t.floor() as usize).min(stops.len() - 2);
+				let s = t - lo as f32;
 
-				Color32::from_rgb(
-					(r.clamp(0.0, 1.0) * 255.0) as u8,
-					(g.clamp(0.0, 1.0) * 255.0) as u8,
-					(b.clamp(0.0, 1.0) * 255.0) as u8,
+				let (a, b) = (stops[lo], stops[lo + 1]);
+				(
+					a.0 + (b.0 - a.0) * s,
+					a.1 + (b.1 - a.1) * s,
+					a.2 + (b.2 - a.2) * s,
 				)
-			},
+			})
+			.collect();
+
+		Self {
+			name: name.into(),
+			colors,
+		}
+	}
+
+	pub fn builtin() -> Vec<Self> {
+		let magma: Vec<_> = (0..COLORMAP_SIZE)
+			.map(|i| magma(i as f32 / (COLORMAP_SIZE - 1) as f32))
+			.collect();
+
+		vec![
+			Self::new("Roseus", &ROSEUS_DATA),
+			Self::new("Arcus", &ARCUS_DATA),
+			Self::new("Lavendula", &LAVENDULA_DATA),
+			Self::new("Magma", &magma),
+		]
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn get_color(&self, t: f32) -> Color32 {
+		let t = t.clamp(0., 1.) * (self.colors.len() - 1) as f32;
+
+		let (r, g, b) = self.colors[t.floor() as usize];
+		Color32::from_rgb((r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8)
+	}
+}
+
+fn magma(t: f32) -> ColorTuple {
+	let t = t.clamp(0.0, 1.0);
+
+	let (r, g, b) = if t < 0.2 {
+		let s = t / 0.2;
+		(0.001 + s * 0.131, 0.0 + s * 0.025, 0.014 + s * 0.227)
+	} else if t < 0.4 {
+		let s = (t - 0.2) / 0.2;
+		(0.132 + s * 0.347, 0.025 + s * 0.041, 0.241 + s * 0.224)
+	} else if t < 0.6 {
+		let s = (t - 0.4) / 0.2;
+		(0.479 + s * 0.286, 0.066 + s * 0.159, 0.465 + s * 0.004)
+	} else if t < 0.8 {
+		let s = (t - 0.6) / 0.2;
+		(0.765 + s * 0.180, 0.225 + s * 0.339, 0.469 - s * 0.169)
+	} else {
+		let s = (t - 0.8) / 0.2;
+		(0.945 + s * 0.046, 0.564 + s * 0.360, 0.300 + s * 0.463)
+	};
+
+	(r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0))
+}
+
+/// A colormap with the display options applied, ready for lookups
+#[derive(Clone)]
+pub struct Palette {
+	id: u64,
+	colors: Arc<[Color32]>,
+}
+
+impl Palette {
+	pub fn new(colormap: &Colormap, reverse: bool, gamma: f32) -> Self {
+		let colors: Arc<[Color32]> = (0..COLORMAP_SIZE)
+			.map(|i| {
+				let t = (i as f32 / (COLORMAP_SIZE - 1) as f32).powf(gamma);
+				colormap.get_color(if reverse { 1. - t } else { t })
+			})
+			.collect();
+
+		let bytes: Vec<u8> = colors.iter().flat_map(|c| c.to_array()).collect();
+
+		Self {
+			id: xxh3_64(&bytes),
+			colors,
 		}
 	}
+
+	pub fn get_color(&self, t: f32) -> Color32 {
+		let i = (t.clamp(0., 1.) * (COLORMAP_SIZE - 1) as f32).round() as usize;
+		self.colors[i]
+	}
 }
 
-fn get_map_color(data: &[ColorTuple], t: f32) -> Color32 {
-	let t = t.clamp(0., 1.) * (data.len() - 1) as f32;
+// Palettes are compared by their contents, so tiles are only recolored when the colors change
+impl PartialEq for Palette {
+	fn eq(&self, other: &Self) -> bool {
+		self.id == other.id
+	}
+}
 
-	let i = t.floor() as usize;
+impl Eq for Palette {}
 
-	let (r, g, b) = data[i];
-	Color32::from_rgb((r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8)
+impl Hash for Palette {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.id.hash(state);
+	}
 }
@@ -0,0 +1,118 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator as _};
+use serde::{Deserialize, Serialize};
+
+// Median filter lengths, harmonic content is smoothed over time and percussive over frequency
+const HPSS_TIME_MS: f64 = 200.;
+const HPSS_FREQ_HZ: f32 = 500.;
+
+const HPSS_MAX_TIME_RADIUS: usize = 20;
+const HPSS_MAX_FREQ_RADIUS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SpectrogramView {
+	Full,
+	Percussive,
+	Harmonic,
+}
+
+impl SpectrogramView {
+	pub fn all() -> &'static [Self] {
+		&[Self::Full, Self::Percussive, Self::Harmonic]
+	}
+}
+
+impl Display for SpectrogramView {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{}",
+			match self {
+				Self::Full => "Full",
+				Self::Percussive => "Percussive only",
+				Self::Harmonic => "Harmonic only",
+			}
+		)
+	}
+}
+
+impl FromStr for SpectrogramView {
+	type Err = eyre::Report;
+
+	fn from_str(s: &str) -> eyre::Result<Self> {
+		Ok(match s.to_lowercase().as_str() {
+			"full" => Self::Full,
+			"percussive" | "p" => Self::Percussive,
+			"harmonic" | "h" => Self::Harmonic,
+			_ => eyre::bail!("unknown spectrogram view: {}", s),
+		})
+	}
+}
+
+/// Half-widths of the median filters, in columns and bins
+pub fn kernel_radii(ms_per_column: f64, sample_rate: u32, fft_size: usize) -> (usize, usize) {
+	let bin_hz = sample_rate as f32 / fft_size as f32;
+
+	let time =
+		((HPSS_TIME_MS / 2. / ms_per_column).round() as usize).clamp(1, HPSS_MAX_TIME_RADIUS);
+	let freq = ((HPSS_FREQ_HZ / 2. / bin_hz).round() as usize).clamp(1, HPSS_MAX_FREQ_RADIUS);
+
+	(time, freq)
+}
+
+fn median(window: &mut [f32]) -> f32 {
+	let mid = window.len() / 2;
+	*window.select_nth_unstable_by(mid, f32::total_cmp).1
+}
+
+/// Median-filtering harmonic/percussive separation on dB columns. The first and last
+/// `time_radius` columns only serve as context and are not part of the output.
+pub fn separate(
+	columns: &[Vec<f32>],
+	view: SpectrogramView,
+	time_radius: usize,
+	freq_radius: usize,
+) -> Vec<Vec<f32>> {
+	if view == SpectrogramView::Full {
+		return columns[time_radius..columns.len() - time_radius].to_vec();
+	}
+
+	let magnitudes: Vec<Vec<f32>> = columns
+		.iter()
+		.map(|column| column.iter().map(|&db| 10_f32.powf(db / 20.)).collect())
+		.collect();
+
+	(time_radius..columns.len() - time_radius)
+		.into_par_iter()
+		.map(|x| {
+			let column = &magnitudes[x];
+			let bins = column.len();
+			let mut window = Vec::with_capacity((time_radius.max(freq_radius)) * 2 + 1);
+
+			(0..bins)
+				.map(|bin| {
+					window.clear();
+					window.extend((x - time_radius..=x + time_radius).map(|i| magnitudes[i][bin]));
+					let harmonic = median(&mut window);
+
+					window.clear();
+					window.extend_from_slice(
+						&column[bin.saturating_sub(freq_radius)..(bin + freq_radius + 1).min(bins)],
+					);
+					let percussive = median(&mut window);
+
+					// Wiener-style soft mask
+					let (h2, p2) = (harmonic * harmonic, percussive * percussive);
+					let mask = match view {
+						SpectrogramView::Percussive => p2 / (p2 + h2 + 1e-20),
+						_ => h2 / (p2 + h2 + 1e-20),
+					};
+
+					columns[x][bin] + 20. * mask.max(1e-10).log10()
+				})
+				.collect()
+		})
+		.collect()
+}
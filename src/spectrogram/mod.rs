@@ -1,20 +1,31 @@
 use std::f32::consts::PI;
 use std::sync::Arc;
 
-use egui::TextureHandle;
-use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator as _};
-use rustfft::num_complex::Complex;
-use rustfft::{Fft, FftPlanner};
+use rayon::iter::{IndexedParallelIterator as _, IntoParallelIterator, ParallelIterator as _};
+use rayon::slice::ParallelSliceMut as _;
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
 
-use crate::audio::AudioData;
+use crate::audio::{AudioData, Channel};
+use crate::spectrogram::hpss::SpectrogramView;
 
+pub mod colormaps;
 pub mod colors;
+pub mod hpss;
+pub mod stats;
+pub mod tiles;
 
 pub struct Spectrogram {
 	pub fft_size: usize,
 	window: Vec<f32>,
-	_planner: FftPlanner<f32>,
-	fft: Arc<dyn Fft<f32>>,
+	fft: Arc<dyn RealToComplex<f32>>,
+}
+
+/// Buffers reused between columns, each worker thread gets its own set
+pub struct StftScratch {
+	input: Vec<f32>,
+	output: Vec<Complex<f32>>,
+	scratch: Vec<Complex<f32>>,
 }
 
 impl Spectrogram {
@@ -23,127 +34,154 @@ impl Spectrogram {
 			.map(|i| 0.5 * (1. - (2. * PI * i as f32 / (fft_size - 1) as f32).cos()))
 			.collect();
 
-		let mut _planner = FftPlanner::new();
-		let fft = _planner.plan_fft_forward(fft_size);
+		let fft = RealFftPlanner::new().plan_fft_forward(fft_size);
 
 		Self {
 			fft_size,
 			window,
-			_planner,
 			fft,
 		}
 	}
 
-	pub fn compute_column(
+	pub fn bins(&self) -> usize {
+		self.fft_size / 2
+	}
+
+	pub fn scratch(&self) -> StftScratch {
+		StftScratch {
+			input: self.fft.make_input_vec(),
+			output: self.fft.make_output_vec(),
+			scratch: self.fft.make_scratch_vec(),
+		}
+	}
+
+	/// Writes the dB magnitudes of the frame centered on `center_sample` into `out`,
+	/// which holds [`Self::bins`] values
+	pub fn compute_column_into(
 		&self,
-		data: &AudioData,
+		samples: &[f32],
 		center_sample: isize,
-		min_db: f32,
-		max_db: f32,
-	) -> Vec<f32> {
-		let half = (self.fft_size / 2) as isize;
-
-		let mut buffer: Vec<_> = (0..self.fft_size)
-			.map(|i| {
-				let idx = center_sample - half + i as isize;
-				let sample = if idx >= 0 && (idx as usize) < data.mono_samples.len() {
-					data.mono_samples[idx as usize]
+		scratch: &mut StftScratch,
+		out: &mut [f32],
+	) {
+		let start = center_sample - (self.fft_size / 2) as isize;
+
+		if start >= 0 && start as usize + self.fft_size <= samples.len() {
+			let frame = &samples[start as usize..start as usize + self.fft_size];
+			for ((input, &sample), &window) in scratch.input.iter_mut().zip(frame).zip(&self.window)
+			{
+				*input = sample * window;
+			}
+		} else {
+			// Frames overlapping either end of the audio are zero-padded
+			for (i, input) in scratch.input.iter_mut().enumerate() {
+				let idx = start + i as isize;
+				let sample = if idx >= 0 && (idx as usize) < samples.len() {
+					samples[idx as usize]
 				} else {
 					0.
 				};
-				Complex::new(sample * self.window[i], 0.)
-			})
-			.collect();
+				*input = sample * self.window[i];
+			}
+		}
+
+		self.fft
+			.process_with_scratch(
+				&mut scratch.input,
+				&mut scratch.output,
+				&mut scratch.scratch,
+			)
+			.expect("buffers are sized for the planned FFT");
+
+		let scale = 2. / self.fft_size as f32;
+		for (out, c) in out.iter_mut().zip(&scratch.output) {
+			*out = 20. * (c.norm() * scale).max(1e-10).log10();
+		}
+	}
 
-		self.fft.process(&mut buffer);
+	pub fn compute_column(&self, samples: &[f32], center_sample: isize) -> Vec<f32> {
+		let mut column = vec![0.; self.bins()];
+		self.compute_column_into(samples, center_sample, &mut self.scratch(), &mut column);
+		column
+	}
 
-		buffer[..self.fft_size / 2]
-			.par_iter()
-			.map(|c| {
-				let mag = c.norm() * 2. / self.fft_size as f32;
-				let db = 20. * mag.max(1e-10).log10();
-				((db - min_db) / (max_db - min_db)).clamp(0., 1.)
-			})
-			.collect()
+	/// Computes `out.len() / bins` consecutive columns into `out`, column after column
+	pub fn compute_columns_into(
+		&self,
+		data: &AudioData,
+		channel: Channel,
+		start_time: f64,
+		ms_per_column: f64,
+		out: &mut [f32],
+	) {
+		let samples = data.channel_samples(channel);
+
+		out.par_chunks_mut(self.bins()).enumerate().for_each_init(
+			|| self.scratch(),
+			|scratch, (i, column)| {
+				let center = start_time + (i as f64 + 0.5) * ms_per_column;
+				let sample = (center / 1000. * data.sample_rate as f64) as isize;
+				self.compute_column_into(&samples, sample, scratch, column);
+			},
+		);
 	}
 
-	pub fn compute_range(
+	pub fn compute_columns(
 		&self,
 		data: &AudioData,
+		channel: Channel,
 		start_time: f64,
-		end_time: f64,
+		ms_per_column: f64,
 		columns: usize,
-		min_db: f32,
-		max_db: f32,
 	) -> Vec<Vec<f32>> {
-		let start_sample = (start_time / 1000. * data.sample_rate as f64) as isize;
-		let end_sample = (end_time / 1000. * data.sample_rate as f64) as isize;
-		let samples_per_column = (end_sample - start_sample) as f64 / columns as f64;
+		let samples = data.channel_samples(channel);
 
 		(0..columns)
 			.into_par_iter()
-			.map(|i| {
-				let sample = start_sample + (i as f64 * samples_per_column) as isize;
-				self.compute_column(data, sample, min_db, max_db)
-			})
+			.map_init(
+				|| self.scratch(),
+				|scratch, i| {
+					let center = start_time + (i as f64 + 0.5) * ms_per_column;
+					let sample = (center / 1000. * data.sample_rate as f64) as isize;
+
+					let mut column = vec![0.; self.bins()];
+					self.compute_column_into(&samples, sample, scratch, &mut column);
+					column
+				},
+			)
 			.collect()
 	}
-}
-
-pub struct CachedSpectrogram {
-	pub texture: TextureHandle,
-	start_time: f64,
-	end_time: f64,
-	fft_size: usize,
-	min_db: f32,
-	max_db: f32,
-	pps: f64,
-}
 
-impl CachedSpectrogram {
-	pub fn new(
-		texture: TextureHandle,
+	/// Like [`Self::compute_columns`], with harmonic/percussive separation applied for
+	/// the separated views. Extra columns are computed on both sides as filter context.
+	pub fn compute_view_columns(
+		&self,
+		data: &AudioData,
+		channel: Channel,
+		view: SpectrogramView,
 		start_time: f64,
-		end_time: f64,
-		fft_size: usize,
-		min_db: f32,
-		max_db: f32,
-		pps: f64,
-	) -> Self {
-		Self {
-			texture,
-			start_time,
-			end_time,
-			min_db,
-			max_db,
-			fft_size,
-			pps,
+		ms_per_column: f64,
+		columns: usize,
+	) -> Vec<Vec<f32>> {
+		if view == SpectrogramView::Full {
+			return self.compute_columns(data, channel, start_time, ms_per_column, columns);
 		}
-	}
 
-	pub fn uv(&self, vis_start: f64, vis_end: f64) -> (f64, f64) {
-		let len = self.end_time - self.start_time;
+		let (time_radius, freq_radius) =
+			hpss::kernel_radii(ms_per_column, data.sample_rate, self.fft_size);
 
-		(
-			(vis_start - self.start_time) / len,
-			(vis_end - self.start_time) / len,
-		)
-	}
+		let context = self.compute_columns(
+			data,
+			channel,
+			start_time - time_radius as f64 * ms_per_column,
+			ms_per_column,
+			columns + time_radius * 2,
+		);
 
-	pub fn is_valid(
-		&self,
-		vis_start: f64,
-		vis_end: f64,
-		fft_size: usize,
-		min_db: f32,
-		max_db: f32,
-		pps: f64,
-	) -> bool {
-		self.start_time <= vis_start
-			&& self.end_time >= vis_end
-			&& self.fft_size == fft_size
-			&& (self.min_db - min_db).abs() < 0.1
-			&& (self.max_db - max_db).abs() < 0.1
-			&& self.pps == pps
+		hpss::separate(&context, view, time_radius, freq_radius)
 	}
 }
+
+pub fn normalize_db(db: f32, min_db: f32, max_db: f32) -> f32 {
+	((db - min_db) / (max_db - min_db)).clamp(0., 1.)
+}
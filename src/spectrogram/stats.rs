@@ -0,0 +1,214 @@
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::audio::{AudioData, Channel};
+use crate::spectrogram::Spectrogram;
+
+// Frames sampled evenly over the whole file for the global statistics
+const STATS_COLUMNS: usize = 1024;
+
+// Histogram buckets are half a dB wide
+const HISTOGRAM_MIN_DB: f32 = -160.;
+const HISTOGRAM_MAX_DB: f32 = 40.;
+const HISTOGRAM_STEP_DB: f32 = 0.5;
+
+// The low percentile sits above the noise floor, the high one ignores a few outliers
+const AUTO_LOW_PERCENTILE: f64 = 0.15;
+const AUTO_HIGH_PERCENTILE: f64 = 0.999;
+const AUTO_MIN_SPAN_DB: f32 = 20.;
+
+// Whitening never boosts or cuts a bin by more than this
+const WHITENING_MAX_DB: f32 = 40.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DbRangeMode {
+	Manual,
+	AutoGlobal,
+	AutoVisible,
+}
+
+impl DbRangeMode {
+	pub fn all() -> &'static [Self] {
+		&[Self::Manual, Self::AutoGlobal, Self::AutoVisible]
+	}
+}
+
+impl Display for DbRangeMode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{}",
+			match self {
+				Self::Manual => "Manual",
+				Self::AutoGlobal => "Auto (track)",
+				Self::AutoVisible => "Auto (visible)",
+			}
+		)
+	}
+}
+
+pub struct DbHistogram {
+	counts: Vec<u32>,
+	total: u64,
+}
+
+impl DbHistogram {
+	pub fn new() -> Self {
+		let buckets = ((HISTOGRAM_MAX_DB - HISTOGRAM_MIN_DB) / HISTOGRAM_STEP_DB) as usize;
+
+		Self {
+			counts: vec![0; buckets],
+			total: 0,
+		}
+	}
+
+	pub fn add(&mut self, db: f32) {
+		let bucket = ((db - HISTOGRAM_MIN_DB) / HISTOGRAM_STEP_DB)
+			.clamp(0., (self.counts.len() - 1) as f32) as usize;
+
+		self.counts[bucket] += 1;
+		self.total += 1;
+	}
+
+	/// Adds every bin of every column, shifted by the whitening offsets if any
+	pub fn add_columns<'a>(
+		&mut self,
+		columns: impl IntoIterator<Item = &'a [f32]>,
+		whitening: Option<&Whitening>,
+	) {
+		for column in columns {
+			for (bin, &db) in column.iter().enumerate() {
+				self.add(whitening.map_or(db, |w| w.apply(db, bin)));
+			}
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.total == 0
+	}
+
+	pub fn percentile(&self, p: f64) -> Option<f32> {
+		if self.total == 0 {
+			return None;
+		}
+
+		let target = (p * self.total as f64).ceil().max(1.) as u64;
+		let mut seen = 0;
+
+		for (bucket, &count) in self.counts.iter().enumerate() {
+			seen += count as u64;
+			if seen >= target {
+				return Some(HISTOGRAM_MIN_DB + (bucket as f32 + 0.5) * HISTOGRAM_STEP_DB);
+			}
+		}
+
+		None
+	}
+
+	/// dB range spanning the interesting part of the distribution, rounded to whole
+	/// dB so small changes don't recolor every tile
+	pub fn auto_range(&self) -> Option<(f32, f32)> {
+		let max = self.percentile(AUTO_HIGH_PERCENTILE)?.round();
+		let min = self
+			.percentile(AUTO_LOW_PERCENTILE)?
+			.round()
+			.min(max - AUTO_MIN_SPAN_DB);
+
+		Some((min, max))
+	}
+}
+
+/// Per-bin dB offsets that flatten the average spectrum of the track
+#[derive(Clone)]
+pub struct Whitening {
+	id: u64,
+	offsets: Arc<[f32]>,
+}
+
+impl Whitening {
+	pub fn from_profile(profile: &[f32]) -> Self {
+		let mean = profile.iter().sum::<f32>() / profile.len().max(1) as f32;
+
+		let offsets: Arc<[f32]> = profile
+			.iter()
+			.map(|&db| (mean - db).clamp(-WHITENING_MAX_DB, WHITENING_MAX_DB))
+			.collect();
+
+		let bytes: Vec<u8> = offsets.iter().flat_map(|o| o.to_le_bytes()).collect();
+
+		Self {
+			id: xxh3_64(&bytes),
+			offsets,
+		}
+	}
+
+	pub fn apply(&self, db: f32, bin: usize) -> f32 {
+		db + self.offsets.get(bin).copied().unwrap_or(0.)
+	}
+}
+
+impl PartialEq for Whitening {
+	fn eq(&self, other: &Self) -> bool {
+		self.id == other.id
+	}
+}
+
+impl Eq for Whitening {}
+
+impl Hash for Whitening {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.id.hash(state);
+	}
+}
+
+/// Magnitude statistics of a whole track, used for the automatic dB range and whitening
+pub struct SpectrumStats {
+	pub hash: u64,
+	pub channel: Channel,
+	pub fft_size: usize,
+	pub whitening: Whitening,
+	histogram: DbHistogram,
+	whitened_histogram: DbHistogram,
+}
+
+impl SpectrumStats {
+	pub fn compute(data: &AudioData, channel: Channel, fft_size: usize) -> Self {
+		let spectrogram = Spectrogram::new(fft_size);
+		let ms_per_column = data.duration / STATS_COLUMNS as f64;
+
+		let columns = spectrogram.compute_columns(data, channel, 0., ms_per_column, STATS_COLUMNS);
+
+		let bins = fft_size / 2;
+		let profile: Vec<f32> = (0..bins)
+			.map(|bin| columns.iter().map(|c| c[bin]).sum::<f32>() / columns.len() as f32)
+			.collect();
+		let whitening = Whitening::from_profile(&profile);
+
+		let mut histogram = DbHistogram::new();
+		histogram.add_columns(columns.iter().map(Vec::as_slice), None);
+
+		let mut whitened_histogram = DbHistogram::new();
+		whitened_histogram.add_columns(columns.iter().map(Vec::as_slice), Some(&whitening));
+
+		Self {
+			hash: data.hash,
+			channel,
+			fft_size,
+			whitening,
+			histogram,
+			whitened_histogram,
+		}
+	}
+
+	pub fn auto_range(&self, whitened: bool) -> Option<(f32, f32)> {
+		if whitened {
+			self.whitened_histogram.auto_range()
+		} else {
+			self.histogram.auto_range()
+		}
+	}
+}
@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use egui::{Color32, ColorImage, TextureHandle};
+use rayon::iter::{IndexedParallelIterator as _, ParallelIterator as _};
+use rayon::slice::ParallelSliceMut as _;
+
+use crate::audio::{AudioData, Channel};
+use crate::cache::DiskCache;
+use crate::spectrogram::colors::Palette;
+use crate::spectrogram::hpss::SpectrogramView;
+use crate::spectrogram::stats::{DbHistogram, Whitening};
+use crate::spectrogram::{Spectrogram, normalize_db};
+
+pub const TILE_COLUMNS: usize = 256;
+
+const MEMORY_BUDGET: usize = 384 * 1024 * 1024;
+const PREFETCH_TILES: i64 = 2;
+
+const TILE_CACHE_MAGIC: &[u8; 4] = b"SPT1";
+
+/// Zoom levels are bucketed by powers of two, so tiles are rendered at
+/// `2^zoom` pixels per second and only ever get downscaled when drawn
+pub fn zoom_bucket(pixels_per_second: f64) -> i32 {
+	pixels_per_second.log2().ceil() as i32
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileKey {
+	pub zoom: i32,
+	pub index: i64,
+	pub fft_size: usize,
+	pub channel: Channel,
+	pub view: SpectrogramView,
+}
+
+impl TileKey {
+	pub fn ms_per_column(&self) -> f64 {
+		1000. / 2_f64.powi(self.zoom)
+	}
+
+	pub fn start_ms(&self) -> f64 {
+		self.index as f64 * TILE_COLUMNS as f64 * self.ms_per_column()
+	}
+
+	pub fn end_ms(&self) -> f64 {
+		(self.index + 1) as f64 * TILE_COLUMNS as f64 * self.ms_per_column()
+	}
+
+	/// Tile and column within it covering `ms` at the given zoom level
+	pub fn at(
+		ms: f64,
+		pixels_per_second: f64,
+		fft_size: usize,
+		channel: Channel,
+		view: SpectrogramView,
+	) -> (Self, usize) {
+		let zoom = zoom_bucket(pixels_per_second);
+		let ms_per_column = 1000. / 2_f64.powi(zoom);
+
+		let column = (ms / ms_per_column).floor().max(0.) as i64;
+		let key = Self {
+			zoom,
+			index: column / TILE_COLUMNS as i64,
+			fft_size,
+			channel,
+			view,
+		};
+
+		(key, (column % TILE_COLUMNS as i64) as usize)
+	}
+
+	fn cache_name(&self, hash: u64) -> String {
+		format!(
+			"{:016x}-{:?}-{:?}-{}-{}-{}.tile",
+			hash, self.channel, self.view, self.fft_size, self.zoom, self.index
+		)
+	}
+}
+
+// Magnitudes are stored on disk in half-dB steps from -127.5 to 0 dB
+fn quantize_db(db: f32) -> u8 {
+	((db + 127.5) * 2.).round().clamp(0., 255.) as u8
+}
+
+fn dequantize_db(value: u8) -> f32 {
+	value as f32 / 2. - 127.5
+}
+
+fn read_cached_tile(reader: &mut impl Read, freq_bins: usize) -> io::Result<Vec<f32>> {
+	let mut header = [0u8; 12];
+	reader.read_exact(&mut header)?;
+
+	let columns = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+	let bins = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+
+	if &header[..4] != TILE_CACHE_MAGIC || columns != TILE_COLUMNS || bins != freq_bins {
+		return Err(io::ErrorKind::InvalidData.into());
+	}
+
+	let mut values = vec![0u8; columns * bins];
+	reader.read_exact(&mut values)?;
+
+	Ok(values.into_iter().map(dequantize_db).collect())
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct TileStyle {
+	palette: Palette,
+	whitening: Option<Whitening>,
+	// dB bounds are stored in tenths of a dB to keep the key hashable
+	min_db: i32,
+	max_db: i32,
+}
+
+impl TileStyle {
+	pub fn new(palette: Palette, whitening: Option<Whitening>, min_db: f32, max_db: f32) -> Self {
+		Self {
+			palette,
+			whitening,
+			min_db: (min_db * 10.).round() as i32,
+			max_db: (max_db * 10.).round() as i32,
+		}
+	}
+
+	fn color(&self, db: f32, bin: usize) -> Color32 {
+		let db = self.whitening.as_ref().map_or(db, |w| w.apply(db, bin));
+		let value = normalize_db(db, self.min_db as f32 / 10., self.max_db as f32 / 10.);
+		self.palette.get_color(value)
+	}
+}
+
+struct CacheEntry<T> {
+	value: T,
+	bytes: usize,
+	last_used: u64,
+}
+
+pub struct TileCache {
+	disk: Arc<DiskCache>,
+
+	// dB values of each tile, column after column
+	magnitudes: HashMap<TileKey, CacheEntry<Arc<Vec<f32>>>>,
+	textures: HashMap<(TileKey, TileStyle), CacheEntry<TextureHandle>>,
+	bytes: usize,
+	frame: u64,
+
+	last_offset: f64,
+	direction: i64,
+}
+
+impl TileCache {
+	pub fn new(disk: Arc<DiskCache>) -> Self {
+		Self {
+			disk,
+
+			magnitudes: HashMap::new(),
+			textures: HashMap::new(),
+			bytes: 0,
+			frame: 0,
+
+			last_offset: 0.,
+			direction: 1,
+		}
+	}
+
+	pub fn clear(&mut self) {
+		self.magnitudes.clear();
+		self.textures.clear();
+		self.bytes = 0;
+	}
+
+	/// Returns the keys of all tiles covering the visible range and
+	/// remembers the scroll direction for prefetching
+	pub fn begin_frame(
+		&mut self,
+		vis_start: f64,
+		vis_end: f64,
+		pixels_per_second: f64,
+		fft_size: usize,
+		view: SpectrogramView,
+		channels: &[Channel],
+	) -> Vec<TileKey> {
+		self.frame += 1;
+
+		if vis_start > self.last_offset {
+			self.direction = 1;
+		} else if vis_start < self.last_offset {
+			self.direction = -1;
+		}
+		self.last_offset = vis_start;
+
+		let zoom = zoom_bucket(pixels_per_second);
+		let tile_ms = TileKey {
+			zoom,
+			index: 1,
+			fft_size,
+			channel: Channel::Mono,
+			view,
+		}
+		.start_ms();
+
+		let first = (vis_start / tile_ms).floor().max(0.) as i64;
+		let last = (vis_end / tile_ms).ceil() as i64;
+
+		channels
+			.iter()
+			.flat_map(|&channel| {
+				(first..last).map(move |index| TileKey {
+					zoom,
+					index,
+					fft_size,
+					channel,
+					view,
+				})
+			})
+			.collect()
+	}
+
+	/// Renders the next uncached tile in the scroll direction, at most
+	/// one per frame to keep the UI responsive
+	pub fn prefetch(
+		&mut self,
+		ctx: &egui::Context,
+		spectrogram: &Spectrogram,
+		data: &AudioData,
+		visible: &[TileKey],
+		style: &TileStyle,
+	) {
+		let direction = self.direction;
+		let edges = visible.iter().filter(|key| {
+			let neighbour = key.index + direction;
+			!visible
+				.iter()
+				.any(|k| k.channel == key.channel && k.index == neighbour)
+		});
+
+		let candidate = edges
+			.flat_map(|&edge| {
+				(1..=PREFETCH_TILES)
+					.map(move |i| TileKey {
+						index: edge.index + i * direction,
+						..edge
+					})
+					.take_while(|key| key.index >= 0 && key.start_ms() < data.duration)
+			})
+			.find(|key| !self.textures.contains_key(&(*key, style.clone())));
+
+		if let Some(key) = candidate {
+			let _ = self.get_or_render(ctx, spectrogram, data, key, style);
+		}
+	}
+
+	pub fn get_or_render(
+		&mut self,
+		ctx: &egui::Context,
+		spectrogram: &Spectrogram,
+		data: &AudioData,
+		key: TileKey,
+		style: &TileStyle,
+	) -> TextureHandle {
+		let frame = self.frame;
+
+		if let Some(entry) = self.textures.get_mut(&(key, style.clone())) {
+			entry.last_used = frame;
+
+			// Keep the raw magnitudes of visible tiles around for readouts
+			if let Some(entry) = self.magnitudes.get_mut(&key) {
+				entry.last_used = frame;
+			}
+
+			return entry.value.clone();
+		}
+
+		let magnitudes = match self.magnitudes.get_mut(&key) {
+			Some(entry) => {
+				entry.last_used = frame;
+				entry.value.clone()
+			},
+			None => {
+				let cache_name = key.cache_name(data.hash);
+				let freq_bins = key.fft_size / 2;
+
+				let cached = self
+					.disk
+					.open(&cache_name)
+					.and_then(|mut reader| read_cached_tile(&mut reader, freq_bins).ok());
+
+				let columns = match cached {
+					Some(columns) => Arc::new(columns),
+					None => {
+						let columns = Arc::new(match key.view {
+							SpectrogramView::Full => {
+								let mut columns = vec![0.; TILE_COLUMNS * freq_bins];
+								spectrogram.compute_columns_into(
+									data,
+									key.channel,
+									key.start_ms(),
+									key.ms_per_column(),
+									&mut columns,
+								);
+								columns
+							},
+							view => spectrogram
+								.compute_view_columns(
+									data,
+									key.channel,
+									view,
+									key.start_ms(),
+									key.ms_per_column(),
+									TILE_COLUMNS,
+								)
+								.concat(),
+						});
+
+						let _columns = columns.clone();
+						self.disk.write(cache_name, move |w| {
+							w.write_all(TILE_CACHE_MAGIC)?;
+							w.write_all(&(TILE_COLUMNS as u32).to_le_bytes())?;
+							w.write_all(&(freq_bins as u32).to_le_bytes())?;
+
+							let values: Vec<u8> =
+								_columns.iter().map(|&db| quantize_db(db)).collect();
+							w.write_all(&values)
+						});
+
+						columns
+					},
+				};
+
+				let bytes = TILE_COLUMNS * freq_bins * size_of::<f32>();
+				self.insert_magnitudes(key, columns.clone(), bytes);
+
+				columns
+			},
+		};
+
+		let freq_bins = key.fft_size / 2;
+		let mut image = ColorImage::filled([TILE_COLUMNS, freq_bins], Default::default());
+
+		image
+			.pixels
+			.par_chunks_mut(TILE_COLUMNS)
+			.enumerate()
+			.for_each(|(y, row)| {
+				let bin = freq_bins - 1 - y;
+				for (x, pixel) in row.iter_mut().enumerate() {
+					*pixel = style.color(magnitudes[x * freq_bins + bin], bin);
+				}
+			});
+
+		let texture = ctx.load_texture(
+			format!("spectrogram-{:?}-{}-{}", key.channel, key.zoom, key.index),
+			image,
+			egui::TextureOptions::LINEAR,
+		);
+
+		let bytes = TILE_COLUMNS * freq_bins * size_of::<Color32>();
+		self.insert_texture(key, style.clone(), texture.clone(), bytes);
+
+		texture
+	}
+
+	/// Raw dB value of a bin in a rendered tile, if its magnitudes are still in memory
+	pub fn magnitude(&self, key: TileKey, column: usize, bin: usize) -> Option<f32> {
+		let bins = key.fft_size / 2;
+		if bin >= bins {
+			return None;
+		}
+
+		self.magnitudes
+			.get(&key)?
+			.value
+			.get(column * bins + bin)
+			.copied()
+	}
+
+	/// Whether the magnitudes of every given tile are in memory
+	pub fn has_magnitudes(&self, keys: &[TileKey]) -> bool {
+		keys.iter().all(|key| self.magnitudes.contains_key(key))
+	}
+
+	/// Histogram of the magnitudes of the given tiles that are in memory,
+	/// sampling every fourth column
+	pub fn histogram(&self, keys: &[TileKey], whitening: Option<&Whitening>) -> DbHistogram {
+		let mut histogram = DbHistogram::new();
+
+		for key in keys {
+			if let Some(entry) = self.magnitudes.get(key) {
+				let columns = entry.value.chunks(key.fft_size / 2).step_by(4);
+				histogram.add_columns(columns, whitening);
+			}
+		}
+
+		histogram
+	}
+
+	fn insert_magnitudes(&mut self, key: TileKey, value: Arc<Vec<f32>>, bytes: usize) {
+		self.bytes += bytes;
+		self.magnitudes.insert(
+			key,
+			CacheEntry {
+				value,
+				bytes,
+				last_used: self.frame,
+			},
+		);
+		self.evict();
+	}
+
+	fn insert_texture(
+		&mut self,
+		key: TileKey,
+		style: TileStyle,
+		value: TextureHandle,
+		bytes: usize,
+	) {
+		self.bytes += bytes;
+		self.textures.insert(
+			(key, style),
+			CacheEntry {
+				value,
+				bytes,
+				last_used: self.frame,
+			},
+		);
+		self.evict();
+	}
+
+	fn evict(&mut self) {
+		while self.bytes > MEMORY_BUDGET {
+			let oldest_magnitudes = self
+				.magnitudes
+				.iter()
+				.filter(|(_, e)| e.last_used < self.frame)
+				.min_by_key(|(_, e)| e.last_used)
+				.map(|(k, e)| (*k, e.last_used));
+			let oldest_texture = self
+				.textures
+				.iter()
+				.filter(|(_, e)| e.last_used < self.frame)
+				.min_by_key(|(_, e)| e.last_used)
+				.map(|(k, e)| (k.clone(), e.last_used));
+
+			// Tiles used during the current frame are never evicted
+			let freed = match (oldest_magnitudes, oldest_texture) {
+				(Some((key, m)), Some((_, t))) if m <= t => {
+					self.magnitudes.remove(&key).map(|e| e.bytes)
+				},
+				(_, Some((key, _))) => self.textures.remove(&key).map(|e| e.bytes),
+				(Some((key, _)), None) => self.magnitudes.remove(&key).map(|e| e.bytes),
+				(None, None) => None,
+			};
+
+			match freed {
+				Some(bytes) => self.bytes -= bytes,
+				None => break,
+			}
+		}
+	}
+}
@@ -0,0 +1,153 @@
+use crate::timing::TimingPoint;
+
+/// Fits timing points to beats placed by hand, one per beat or, with `per_measure`,
+/// one per measure of `beats_per_measure` markers. Neighbouring sections whose BPM
+/// is within `tolerance_bpm` of each other are merged, with the BPM of the merged
+/// section fitted so that its first and last marker stay in place. Markers are
+/// rounded to whole milliseconds first, closer ones counting as one. BPMs are kept
+/// within 1..=999 like any other timing point.
+pub fn fit_tempo_map(
+	markers: &[f64],
+	beats_per_measure: u32,
+	per_measure: bool,
+	tolerance_bpm: f64,
+) -> Vec<TimingPoint> {
+	// Offsets are whole milliseconds, so the BPM is fitted between the rounded
+	// markers for sections not to drift
+	let mut markers: Vec<f64> = markers.iter().map(|m| m.round()).collect();
+	markers.sort_by(f64::total_cmp);
+	markers.dedup();
+
+	if markers.len() < 2 {
+		return vec![];
+	}
+
+	let step = if per_measure {
+		beats_per_measure.max(1) as usize
+	} else {
+		1
+	};
+
+	// Markers starting each section, the last one closing a shorter section if the
+	// markers don't fill a whole measure
+	let mut bounds: Vec<usize> = (0..markers.len()).step_by(step).collect();
+	if bounds.last() != Some(&(markers.len() - 1)) {
+		bounds.push(markers.len() - 1);
+	}
+
+	let bpm = |(start, end): (usize, usize)| {
+		60000. * (end - start) as f64 / (markers[end] - markers[start])
+	};
+
+	let mut runs: Vec<(usize, usize)> = vec![];
+	for pair in bounds.windows(2) {
+		let section = (pair[0], pair[1]);
+
+		match runs.last_mut() {
+			Some(run) if (bpm(section) - bpm(*run)).abs() <= tolerance_bpm => run.1 = section.1,
+			_ => runs.push(section),
+		}
+	}
+
+	runs.into_iter()
+		.map(|run| {
+			let mut tp = TimingPoint::new(markers[run.0], bpm(run).clamp(1., 999.));
+			if per_measure {
+				tp.signature.0 = beats_per_measure;
+			}
+			tp
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn beats(start: f64, bpm: f64, count: usize) -> Vec<f64> {
+		(0..count)
+			.map(|i| start + i as f64 * 60000. / bpm)
+			.collect()
+	}
+
+	fn assert_point(tp: &TimingPoint, offset: f64, bpm: f64) {
+		assert_eq!(tp.offset, offset);
+		assert!((tp.bpm - bpm).abs() < 1e-9, "{} != {}", tp.bpm, bpm);
+	}
+
+	#[test]
+	fn constant_tempo() {
+		let fitted = fit_tempo_map(&beats(1000., 120., 16), 4, false, 1.);
+
+		assert_eq!(fitted.len(), 1);
+		assert_point(&fitted[0], 1000., 120.);
+	}
+
+	#[test]
+	fn tempo_change_outside_tolerance() {
+		let mut markers = beats(0., 120., 8);
+		markers.extend(beats(3500., 150., 8).into_iter().skip(1));
+		let fitted = fit_tempo_map(&markers, 4, false, 1.);
+
+		assert_eq!(fitted.len(), 2);
+		assert_point(&fitted[0], 0., 120.);
+		assert_point(&fitted[1], 3500., 150.);
+	}
+
+	#[test]
+	fn tempo_change_inside_tolerance() {
+		// 120 BPM then 120.5 BPM, merged into one section through both ends
+		let mut markers = beats(0., 120., 5);
+		markers.extend(beats(2000., 60000. / 498., 5).into_iter().skip(1));
+		let fitted = fit_tempo_map(&markers, 4, false, 1.);
+
+		assert_eq!(fitted.len(), 1);
+		assert_point(&fitted[0], 0., 60000. * 8. / 3992.);
+	}
+
+	#[test]
+	fn per_measure() {
+		let mut markers = beats(0., 120., 9);
+		markers.extend(beats(4000., 100., 5).into_iter().skip(1));
+		let fitted = fit_tempo_map(&markers, 4, true, 1.);
+
+		assert_eq!(fitted.len(), 2);
+		assert_point(&fitted[0], 0., 120.);
+		assert_point(&fitted[1], 4000., 100.);
+		assert!(fitted.iter().all(|tp| tp.signature.0 == 4));
+	}
+
+	#[test]
+	fn rounded_offsets_keep_sections_in_place() {
+		let markers = [0.4, 333.6, 666.4, 1000.2];
+		let fitted = fit_tempo_map(&markers, 4, false, 5.);
+
+		assert_eq!(fitted.len(), 1);
+		let tp = &fitted[0];
+		assert_eq!(tp.offset, 0.);
+		assert!((tp.offset + 3. * tp.ms_per_beat() - 1000.).abs() < 1e-9);
+	}
+
+	#[test]
+	fn bpm_is_clamped() {
+		let fitted = fit_tempo_map(&[0., 2., 4.], 4, false, 1.);
+
+		assert_eq!(fitted.len(), 1);
+		assert_eq!(fitted[0].bpm, 999.);
+	}
+
+	#[test]
+	fn too_few_markers() {
+		assert!(fit_tempo_map(&[], 4, false, 1.).is_empty());
+		assert!(fit_tempo_map(&[500.], 4, false, 1.).is_empty());
+	}
+
+	#[test]
+	fn duplicate_markers() {
+		assert!(fit_tempo_map(&[500., 500.2], 4, false, 1.).is_empty());
+
+		let fitted = fit_tempo_map(&[0., 500., 500., 1000., 999.8], 4, false, 1.);
+		assert_eq!(fitted.len(), 1);
+		assert_point(&fitted[0], 0., 120.);
+	}
+}
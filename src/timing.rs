@@ -1,16 +1,125 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
 use egui::Color32;
+use serde::{Deserialize, Serialize};
 
 use crate::colors::{
 	COLOR_SNAP_BEAT, COLOR_SNAP_EIGHTH, COLOR_SNAP_HALF, COLOR_SNAP_OTHER, COLOR_SNAP_QUARTER,
 	COLOR_SNAP_SIXTEENTH, COLOR_SNAP_SIXTH, COLOR_SNAP_THIRD, COLOR_SNAP_TWELFTH,
 };
 
+/// A tempo section. The BPM counts notes of the signature's denominator, so a 6/8
+/// section at 180 BPM has eighth notes 333 ms apart.
 #[derive(Clone, Copy, PartialEq)]
 pub struct TimingPoint {
 	id: egui::Id,
 	pub offset: f64,
 	pub bpm: f64,
 	pub signature: (u32, u32),
+	/// Beats of the measure that start a group, as a bitmask. 0 uses the default
+	/// grouping for the signature.
+	pub grouping: u32,
+	/// Share of each swing pair taken by its first note, 0.5 is straight
+	pub swing: f64,
+	pub swing_unit: SwingUnit,
+	/// How the tempo moves towards the next timing point's BPM
+	pub ramp: TempoRamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempoRamp {
+	Constant,
+	/// BPM changes at a constant rate over time
+	Linear,
+	/// BPM changes by a constant ratio over time
+	Exponential,
+}
+
+impl TempoRamp {
+	pub fn all() -> &'static [Self] {
+		&[Self::Constant, Self::Linear, Self::Exponential]
+	}
+}
+
+impl Display for TempoRamp {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{}",
+			match self {
+				Self::Constant => "Constant",
+				Self::Linear => "Linear",
+				Self::Exponential => "Exponential",
+			}
+		)
+	}
+}
+
+impl FromStr for TempoRamp {
+	type Err = eyre::Report;
+
+	fn from_str(s: &str) -> eyre::Result<Self> {
+		Ok(match s.to_lowercase().as_str() {
+			"constant" => Self::Constant,
+			"linear" => Self::Linear,
+			"exponential" => Self::Exponential,
+			_ => eyre::bail!("unknown tempo ramp: {}", s),
+		})
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwingUnit {
+	Eighth,
+	Sixteenth,
+}
+
+impl SwingUnit {
+	pub fn all() -> &'static [Self] {
+		&[Self::Eighth, Self::Sixteenth]
+	}
+
+	/// Length of a swing pair in beats
+	fn pair_beats(self) -> f64 {
+		match self {
+			Self::Eighth => 1.,
+			Self::Sixteenth => 0.5,
+		}
+	}
+
+	/// Notes of this length per beat
+	pub fn per_beat(self) -> i64 {
+		match self {
+			Self::Eighth => 2,
+			Self::Sixteenth => 4,
+		}
+	}
+}
+
+impl Display for SwingUnit {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{}",
+			match self {
+				Self::Eighth => "8ths",
+				Self::Sixteenth => "16ths",
+			}
+		)
+	}
+}
+
+impl FromStr for SwingUnit {
+	type Err = eyre::Report;
+
+	fn from_str(s: &str) -> eyre::Result<Self> {
+		Ok(match s {
+			"8" | "8ths" => Self::Eighth,
+			"16" | "16ths" => Self::Sixteenth,
+			_ => eyre::bail!("unknown swing unit: {}", s),
+		})
+	}
 }
 
 impl TimingPoint {
@@ -20,6 +129,10 @@ impl TimingPoint {
 			offset,
 			bpm,
 			signature: (4, 4),
+			grouping: 0,
+			swing: 0.5,
+			swing_unit: SwingUnit::Eighth,
+			ramp: TempoRamp::Constant,
 		}
 	}
 
@@ -27,14 +140,504 @@ impl TimingPoint {
 		self.id
 	}
 
+	/// A new timing point at `offset` with the same settings as this one
+	pub fn duplicate_at(&self, offset: f64) -> Self {
+		Self {
+			id: egui::Id::new(rand::random::<u128>()),
+			offset,
+			..*self
+		}
+	}
+
 	pub fn ms_per_beat(&self) -> f64 {
 		60000. / self.bpm
 	}
+
+	/// Length of the section and the BPM it ramps to, if it ramps into `next`
+	fn ramp_to(&self, next: Option<&TimingPoint>) -> Option<(f64, f64)> {
+		let next = next?;
+		let length = next.offset - self.offset;
+
+		(self.ramp != TempoRamp::Constant && length > 0. && (next.bpm - self.bpm).abs() > 1e-9)
+			.then_some((length, next.bpm))
+	}
+
+	pub fn ramps_into(&self, next: Option<&TimingPoint>) -> bool {
+		self.ramp_to(next).is_some()
+	}
+
+	/// Average BPM over the section ended by `next`
+	pub fn mean_bpm(&self, next: Option<&TimingPoint>) -> f64 {
+		match self.ramp_to(next) {
+			None => self.bpm,
+			Some((_, end)) => match self.ramp {
+				TempoRamp::Exponential => (end - self.bpm) / (end / self.bpm).ln(),
+				_ => (self.bpm + end) / 2.,
+			},
+		}
+	}
+
+	pub fn bpm_at(&self, ms: f64, next: Option<&TimingPoint>) -> f64 {
+		let Some((length, end)) = self.ramp_to(next) else {
+			return self.bpm;
+		};
+
+		let t = ((ms - self.offset) / length).clamp(0., 1.);
+		match self.ramp {
+			TempoRamp::Exponential => self.bpm * (end / self.bpm).powf(t),
+			_ => self.bpm + (end - self.bpm) * t,
+		}
+	}
+
+	/// Beats from the offset to `ms`, following the ramp into `next`. The tempo holds
+	/// steady before the offset and past the end of the section.
+	pub fn beats_at(&self, ms: f64, next: Option<&TimingPoint>) -> f64 {
+		let t = ms - self.offset;
+		let Some((length, end)) = self.ramp_to(next).filter(|_| t > 0.) else {
+			return t / self.ms_per_beat();
+		};
+
+		let ramped = t.min(length);
+		let beats = match self.ramp {
+			TempoRamp::Exponential => {
+				let ln = (end / self.bpm).ln();
+				self.bpm * length / ln * ((ln * ramped / length).exp() - 1.)
+			},
+			_ => self.bpm * ramped + (end - self.bpm) * ramped * ramped / (2. * length),
+		} / 60000.;
+
+		beats + (t - ramped) * end / 60000.
+	}
+
+	/// Time of the beat `beats` after the offset, the inverse of [`Self::beats_at`]
+	pub fn ms_at(&self, beats: f64, next: Option<&TimingPoint>) -> f64 {
+		let Some((length, end)) = self.ramp_to(next).filter(|_| beats > 0.) else {
+			return self.offset + beats * self.ms_per_beat();
+		};
+
+		let total = self.mean_bpm(next) * length / 60000.;
+		if beats >= total {
+			return self.offset + length + (beats - total) * 60000. / end;
+		}
+
+		let b = beats * 60000.;
+		let t = match self.ramp {
+			TempoRamp::Exponential => {
+				let ln = (end / self.bpm).ln();
+				length / ln * (1. + b * ln / (self.bpm * length)).ln()
+			},
+			_ => {
+				let k = (end - self.bpm) / (2. * length);
+				2. * b / (self.bpm + (self.bpm * self.bpm + 4. * k * b).sqrt())
+			},
+		};
+
+		self.offset + t
+	}
+
+	/// Starting BPM that puts the beat `beats` after the offset at `ms`
+	pub fn bpm_for_beat(&self, beats: f64, ms: f64, next: Option<&TimingPoint>) -> f64 {
+		if !self.ramps_into(next) {
+			return 60000. * beats / (ms - self.offset);
+		}
+
+		// Beats reached by `ms` grow with the starting BPM, so bisect on it
+		let (mut low, mut high) = (1., 999.);
+		for _ in 0..50 {
+			let mid = (low + high) / 2.;
+			let tp = TimingPoint { bpm: mid, ..*self };
+
+			if tp.beats_at(ms, next) < beats {
+				low = mid;
+			} else {
+				high = mid;
+			}
+		}
+
+		(low + high) / 2.
+	}
+
+	pub fn is_swung(&self) -> bool {
+		(self.swing - 0.5).abs() > 1e-6
+	}
+
+	/// Moves a position in beats from the offset from the straight grid onto the
+	/// swung one, stretching the first half of every swing pair to `swing` of it
+	pub fn swing_beats(&self, beats: f64) -> f64 {
+		if !self.is_swung() {
+			return beats;
+		}
+
+		let pair = self.swing_unit.pair_beats();
+		let index = (beats / pair).floor();
+		let t = beats / pair - index;
+
+		let t = if t < 0.5 {
+			t * self.swing / 0.5
+		} else {
+			self.swing + (t - 0.5) * (1. - self.swing) / 0.5
+		};
+
+		(index + t) * pair
+	}
+
+	/// Inverse of [`Self::swing_beats`]
+	pub fn unswing_beats(&self, beats: f64) -> f64 {
+		if !self.is_swung() {
+			return beats;
+		}
+
+		let pair = self.swing_unit.pair_beats();
+		let index = (beats / pair).floor();
+		let t = beats / pair - index;
+
+		let t = if t < self.swing {
+			t * 0.5 / self.swing
+		} else {
+			0.5 + (t - self.swing) * 0.5 / (1. - self.swing)
+		};
+
+		(index + t) * pair
+	}
+
+	/// Beats of the measure that start a group, falling back to the default grouping
+	/// if the stored one doesn't fit the numerator
+	pub fn group_starts(&self) -> u32 {
+		let beats = self.signature.0;
+		if self.grouping & 1 == 1 && self.grouping >> beats.min(31) == 0 {
+			self.grouping
+		} else {
+			default_grouping(self.signature)
+		}
+	}
+
+	pub fn is_group_start(&self, beat_in_measure: i64) -> bool {
+		(self.group_starts() >> beat_in_measure.clamp(0, 31)) & 1 == 1
+	}
+
+	pub fn grouping_text(&self) -> String {
+		grouping_to_text(self.group_starts(), self.signature.0)
+	}
+
+	/// Sets the grouping from text like "2+2+3", returning false if the groups don't
+	/// add up to the numerator
+	pub fn set_grouping_text(&mut self, text: &str) -> bool {
+		let Ok(groups) = text
+			.split('+')
+			.map(|group| group.trim().parse::<u32>())
+			.collect::<Result<Vec<_>, _>>()
+		else {
+			return false;
+		};
+
+		match groups_to_mask(&groups, self.signature.0) {
+			Some(mask) => {
+				self.grouping = mask;
+				true
+			},
+			None => false,
+		}
+	}
+}
+
+/// Compound meters (6/8, 9/8, 12/8) are grouped in threes and other eighth-note
+/// meters in twos with a three at the end, e.g. 7/8 as 2+2+3. Quarter-note meters
+/// accent every beat.
+pub fn default_grouping((beats, denominator): (u32, u32)) -> u32 {
+	let beats = beats.clamp(1, 31);
+	if denominator < 8 || beats <= 3 {
+		return (1 << beats) - 1;
+	}
+
+	let groups: Vec<u32> = if beats % 3 == 0 {
+		vec![3; beats as usize / 3]
+	} else {
+		let mut groups = vec![2; beats as usize / 2];
+		if beats % 2 == 1 {
+			*groups.last_mut().unwrap() = 3;
+		}
+		groups
+	};
+
+	groups_to_mask(&groups, beats).unwrap_or(1)
+}
+
+fn groups_to_mask(groups: &[u32], beats: u32) -> Option<u32> {
+	if groups.contains(&0) || groups.iter().sum::<u32>() != beats || beats > 31 {
+		return None;
+	}
+
+	let mut mask = 0;
+	let mut start = 0;
+	for group in groups {
+		mask |= 1 << start;
+		start += group;
+	}
+
+	Some(mask)
+}
+
+/// Group lengths of a grouping mask, e.g. "2+2+3"
+pub fn grouping_to_text(mask: u32, beats: u32) -> String {
+	let starts: Vec<u32> = (0..beats).filter(|i| (mask >> i) & 1 == 1).collect();
+
+	starts
+		.iter()
+		.zip(starts.iter().skip(1).chain([&beats]))
+		.map(|(start, end)| (end - start).to_string())
+		.collect::<Vec<_>>()
+		.join("+")
+}
+
+/// Every way of splitting a measure into groups of two and three beats, plus one
+/// group per beat, for picking a grouping
+pub fn grouping_options(beats: u32) -> Vec<u32> {
+	fn split(beats: u32, start: u32, mask: u32, out: &mut Vec<u32>) {
+		if start == beats {
+			out.push(mask);
+			return;
+		}
+
+		for group in [2, 3] {
+			if start + group <= beats {
+				split(beats, start + group, mask | (1 << start), out);
+			}
+		}
+	}
+
+	let beats = beats.clamp(1, 16);
+	let mut options = vec![(1 << beats) - 1];
+	split(beats, 0, 0, &mut options);
+	options
+}
+
+/// Index of the timing point in effect at `ms`, or of the first one before any of them
+pub fn section_index(timing_points: &[TimingPoint], ms: f64) -> Option<usize> {
+	timing_points
+		.iter()
+		.rposition(|tp| tp.offset <= ms)
+		.or((!timing_points.is_empty()).then_some(0))
+}
+
+/// Timing point in effect at `ms`, or the first one before any of them
+pub fn section_at(timing_points: &[TimingPoint], ms: f64) -> Option<&TimingPoint> {
+	timing_points.get(section_index(timing_points, ms)?)
+}
+
+/// Musical position of `ms` as a measure and a fractional beat within it, both
+/// counted from 1 at the timing point in effect
+pub fn musical_position(timing_points: &[TimingPoint], ms: f64) -> Option<(i64, f64)> {
+	let idx = section_index(timing_points, ms)?;
+	let tp = &timing_points[idx];
+
+	let beats = tp.beats_at(ms, timing_points.get(idx + 1));
+	let beats_per_measure = tp.signature.0 as f64;
+
+	let measure = (beats / beats_per_measure).floor();
+	let beat = beats - measure * beats_per_measure;
+
+	Some((measure as i64 + 1, beat + 1.))
+}
+
+/// Start and end of the measure containing `ms`, measures are cut short by the
+/// next timing point
+pub fn measure_bounds(timing_points: &[TimingPoint], ms: f64) -> Option<(f64, f64)> {
+	let idx = section_index(timing_points, ms)?;
+	let (tp, next) = (&timing_points[idx], timing_points.get(idx + 1));
+
+	let beats_per_measure = tp.signature.0 as f64;
+	let measure = (tp.beats_at(ms, next) / beats_per_measure).floor();
+
+	let start = tp.ms_at(measure * beats_per_measure, next);
+	let mut end = tp.ms_at((measure + 1.) * beats_per_measure, next);
+	if let Some(next) = next {
+		end = end.min(next.offset);
+	}
+
+	Some((start, end))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResnapMode {
+	Off,
+	Beat,
+	Measure,
+}
+
+impl ResnapMode {
+	pub fn all() -> &'static [Self] {
+		&[Self::Off, Self::Beat, Self::Measure]
+	}
+}
+
+impl Display for ResnapMode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"{}",
+			match self {
+				Self::Off => "Off",
+				Self::Beat => "Nearest beat",
+				Self::Measure => "Nearest measure",
+			}
+		)
+	}
+}
+
+/// Moves the timing point after `index` onto the nearest beat or measure start of
+/// section `index`, shifting every later point by the same amount so their own
+/// sections are left intact. Returns each moved point before and after.
+pub fn resnap_following(
+	timing_points: &mut [TimingPoint],
+	index: usize,
+	mode: ResnapMode,
+) -> Vec<(TimingPoint, TimingPoint)> {
+	let (Some(tp), Some(next)) = (timing_points.get(index), timing_points.get(index + 1)) else {
+		return vec![];
+	};
+
+	// A ramp covers beats in proportion to its length for fixed end tempos
+	let ms_per_beat = 60000. / tp.mean_bpm(Some(next));
+	let unit = match mode {
+		ResnapMode::Off => return vec![],
+		ResnapMode::Beat => ms_per_beat,
+		ResnapMode::Measure => ms_per_beat * tp.signature.0 as f64,
+	};
+
+	// A section is never snapped down to nothing
+	let units = ((next.offset - tp.offset) / unit).round().max(1.);
+	let delta = tp.offset + units * unit - next.offset;
+	if delta.abs() < 1e-6 {
+		return vec![];
+	}
+
+	timing_points[index + 1..]
+		.iter_mut()
+		.map(|tp| {
+			let before = *tp;
+			tp.offset += delta;
+			(before, *tp)
+		})
+		.collect()
+}
+
+/// Sections whose length is more than `tolerance_ms` away from a whole number of
+/// beats, as the index of their timing point and their length in beats
+pub fn fractional_sections(timing_points: &[TimingPoint], tolerance_ms: f64) -> Vec<(usize, f64)> {
+	timing_points
+		.windows(2)
+		.enumerate()
+		.filter_map(|(i, pair)| {
+			let (tp, next) = (&pair[0], Some(&pair[1]));
+			let beats = tp.beats_at(pair[1].offset, next);
+			let error = (beats - beats.round()).abs() * 60000. / tp.mean_bpm(next);
+
+			(error > tolerance_ms).then_some((i, beats))
+		})
+		.collect()
+}
+
+/// Replaces tempo ramps by constant steps of `step_beats` beats, each with the
+/// tempo that reaches the next step on time, for formats without ramps. Steps that
+/// start inside a measure are flagged.
+pub fn bake_ramps(timing_points: &[TimingPoint], step_beats: f64) -> Vec<(TimingPoint, bool)> {
+	let mut baked = vec![];
+
+	for (i, tp) in timing_points.iter().enumerate() {
+		let next = timing_points.get(i + 1);
+		let Some(end_ms) = next.filter(|_| tp.ramps_into(next)).map(|next| next.offset) else {
+			baked.push((*tp, false));
+			continue;
+		};
+
+		let total = tp.beats_at(end_ms, next);
+		let beats_per_measure = tp.signature.0 as f64;
+		let mut beat = 0.;
+
+		while beat < total - 1e-6 {
+			let step_end = (beat + step_beats).min(total);
+			let (start_ms, step_end_ms) = (tp.ms_at(beat, next), tp.ms_at(step_end, next));
+
+			let mut step = *tp;
+			step.offset = start_ms;
+			step.bpm = 60000. * (step_end - beat) / (step_end_ms - start_ms);
+			step.ramp = TempoRamp::Constant;
+
+			let in_measure = (beat / beats_per_measure).fract() > 1e-6;
+			baked.push((step, beat > 0. && in_measure));
+
+			beat = step_end;
+		}
+	}
+
+	baked
+}
+
+/// Collects the ticks of every timing point's section within `start..end`,
+/// sections are cut off at the next timing point or at `duration`
+pub fn beat_ticks(
+	timing_points: &[TimingPoint],
+	snap_divisor: i64,
+	start: f64,
+	end: f64,
+	duration: f64,
+) -> Vec<(f64, SnapDivision)> {
+	let mut ticks = vec![];
+
+	for (i, tp) in timing_points.iter().enumerate() {
+		let next = timing_points.get(i + 1);
+
+		// TODO: stop rendering at low zoom
+
+		let section_end = if i + 1 < timing_points.len() {
+			timing_points[i + 1].offset
+		} else {
+			duration
+		};
+
+		let tick_start = start.max(tp.offset);
+		let tick_end = end.min(section_end);
+
+		if tick_start >= tick_end {
+			continue;
+		}
+
+		// Swing only ever moves ticks later, so the straight grid bounds the search
+		let beats_from_start = (tp.beats_at(tick_start, next) * snap_divisor as f64).floor() as i64;
+		let tick_at =
+			|count: i64| tp.ms_at(tp.swing_beats(count as f64 / snap_divisor as f64), next);
+
+		let mut tick_ms = tick_at(beats_from_start);
+		let mut tick_count = beats_from_start;
+
+		while tick_ms <= tick_end {
+			if tick_ms >= tick_start && tick_ms < section_end {
+				let in_beat = tick_count.rem_euclid(snap_divisor);
+				let beat_num = tick_count.div_euclid(snap_divisor);
+				let in_measure = beat_num.rem_euclid(tp.signature.0 as i64);
+
+				let snap = SnapDivision::from_tick(
+					in_beat,
+					snap_divisor,
+					in_measure,
+					tp.is_group_start(in_measure),
+				);
+
+				ticks.push((tick_ms, snap));
+			}
+			tick_count += 1;
+			tick_ms = tick_at(tick_count);
+		}
+	}
+
+	ticks
 }
 
 pub enum SnapDivision {
 	Downbeat,
 	Beat,
+	/// A beat inside a group, e.g. the second eighth of a dotted quarter in 6/8
+	Pulse,
 	Half,
 	Third,
 	Quarter,
@@ -48,7 +651,7 @@ pub enum SnapDivision {
 impl SnapDivision {
 	pub fn color(&self) -> Color32 {
 		let color = match self {
-			Self::Downbeat | Self::Beat => COLOR_SNAP_BEAT,
+			Self::Downbeat | Self::Beat | Self::Pulse => COLOR_SNAP_BEAT,
 			Self::Half => COLOR_SNAP_HALF,
 			Self::Third => COLOR_SNAP_THIRD,
 			Self::Quarter => COLOR_SNAP_QUARTER,
@@ -68,6 +671,7 @@ impl SnapDivision {
 		match self {
 			Self::Downbeat => 1.,
 			Self::Beat => 0.8,
+			Self::Pulse => 0.7,
 			Self::Half => 0.65,
 			Self::Third => 0.55,
 			Self::Quarter => 0.48,
@@ -83,16 +687,19 @@ impl SnapDivision {
 		match self {
 			Self::Downbeat => 2.5,
 			Self::Beat => 2.,
-			Self::Half => 1.5,
+			Self::Pulse | Self::Half => 1.5,
 			_ => 1.,
 		}
 	}
 
-	pub fn from_tick(in_beat: i64, divisor: i64, in_measure: i64) -> Self {
+	pub fn from_tick(in_beat: i64, divisor: i64, in_measure: i64, group_start: bool) -> Self {
 		if in_beat == 0 {
 			if in_measure == 0 {
 				return Self::Downbeat;
 			}
+			if !group_start {
+				return Self::Pulse;
+			}
 			return Self::Beat;
 		}
 
@@ -120,3 +727,235 @@ impl SnapDivision {
 		Self::Other
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ramp(ramp: TempoRamp) -> [TimingPoint; 2] {
+		let mut tp = TimingPoint::new(1000., 120.);
+		tp.ramp = ramp;
+		[tp, TimingPoint::new(9000., 180.)]
+	}
+
+	fn assert_close(a: f64, b: f64) {
+		assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+	}
+
+	#[test]
+	fn ms_at_inverts_beats_at() {
+		for kind in TempoRamp::all() {
+			let [tp, next] = ramp(*kind);
+
+			for ms in [0., 1000., 1500., 4321., 8999., 9000., 12000.] {
+				assert_close(tp.ms_at(tp.beats_at(ms, Some(&next)), Some(&next)), ms);
+			}
+		}
+	}
+
+	#[test]
+	fn ramps_reach_the_next_bpm() {
+		for kind in [TempoRamp::Linear, TempoRamp::Exponential] {
+			let [tp, next] = ramp(kind);
+
+			assert_close(tp.bpm_at(1000., Some(&next)), 120.);
+			assert_close(tp.bpm_at(9000., Some(&next)), 180.);
+			assert_close(
+				tp.beats_at(9000., Some(&next)),
+				tp.mean_bpm(Some(&next)) * 8000. / 60000.,
+			);
+		}
+	}
+
+	#[test]
+	fn degenerate_ramps_are_constant() {
+		let constant = TimingPoint::new(1000., 120.);
+
+		for kind in [TempoRamp::Linear, TempoRamp::Exponential] {
+			let [mut tp, _] = ramp(kind);
+			let same_bpm = TimingPoint::new(9000., 120.);
+			let same_offset = TimingPoint::new(1000., 180.);
+			tp.bpm = 120.;
+
+			for next in [same_bpm, same_offset] {
+				assert!(!tp.ramps_into(Some(&next)));
+				for ms in [0., 2500., 9000., 12000.] {
+					assert_close(tp.beats_at(ms, Some(&next)), constant.beats_at(ms, None));
+				}
+				assert_close(tp.ms_at(10., Some(&next)), constant.ms_at(10., None));
+			}
+		}
+	}
+
+	#[test]
+	fn bpm_for_beat_lands_on_the_beat() {
+		for kind in TempoRamp::all() {
+			let [tp, next] = ramp(*kind);
+			let bpm = tp.bpm_for_beat(16., 8000., Some(&next));
+			let fitted = TimingPoint { bpm, ..tp };
+
+			assert!((fitted.beats_at(8000., Some(&next)) - 16.).abs() < 1e-6);
+		}
+	}
+
+	#[test]
+	fn baked_steps_land_on_the_ramp() {
+		for kind in [TempoRamp::Linear, TempoRamp::Exponential] {
+			let [tp, next] = ramp(kind);
+			let baked = bake_ramps(&[tp, next], 1.);
+			let total = tp.beats_at(next.offset, Some(&next));
+
+			assert_eq!(baked.len(), total.ceil() as usize + 1);
+			for (i, pair) in baked.windows(2).enumerate() {
+				let (step, _) = pair[0];
+				let step_end = pair[1].0.offset;
+
+				assert_eq!(step.ramp, TempoRamp::Constant);
+				assert_close(step.offset, tp.ms_at(i as f64, Some(&next)));
+				let beats = (i as f64 + 1.).min(total) - i as f64;
+				assert_close(step.offset + beats * step.ms_per_beat(), step_end);
+			}
+
+			let (last, flagged) = baked.last().unwrap();
+			assert!(*last == next);
+			assert!(!flagged);
+		}
+	}
+
+	#[test]
+	fn baked_steps_inside_a_measure_are_flagged() {
+		let [tp, next] = ramp(TempoRamp::Linear);
+		let baked = bake_ramps(&[tp, next], 1.);
+
+		for (i, (_, flagged)) in baked[..baked.len() - 1].iter().enumerate() {
+			assert_eq!(*flagged, i % 4 != 0, "step {}", i);
+		}
+	}
+
+	fn signature(beats: u32, denominator: u32) -> TimingPoint {
+		let mut tp = TimingPoint::new(0., 120.);
+		tp.signature = (beats, denominator);
+		tp
+	}
+
+	#[test]
+	fn default_groupings() {
+		assert_eq!(grouping_to_text(default_grouping((4, 4)), 4), "1+1+1+1");
+		assert_eq!(grouping_to_text(default_grouping((6, 8)), 6), "3+3");
+		assert_eq!(grouping_to_text(default_grouping((7, 8)), 7), "2+2+3");
+		assert_eq!(grouping_to_text(default_grouping((12, 8)), 12), "3+3+3+3");
+		assert_eq!(signature(7, 8).grouping_text(), "2+2+3");
+	}
+
+	#[test]
+	fn grouping_text_round_trips() {
+		for (text, beats, mask) in [("3+3+2", 8, 0b1001001), ("2+2+3", 7, 0b10101)] {
+			let mut tp = signature(beats, 8);
+			assert!(tp.set_grouping_text(text));
+			assert_eq!(tp.grouping, mask);
+			assert_eq!(tp.grouping_text(), text);
+			assert_eq!(grouping_to_text(mask, beats), text);
+		}
+
+		let mut tp = signature(7, 8);
+		assert!(tp.set_grouping_text(" 2 + 2 + 3 "));
+		assert_eq!(tp.grouping, 0b10101);
+	}
+
+	#[test]
+	fn invalid_groupings_are_rejected() {
+		let mut tp = signature(7, 8);
+
+		for text in ["3+3", "2+2+2+2", "3+0+4", "2+x+3", ""] {
+			assert!(!tp.set_grouping_text(text), "{}", text);
+		}
+		assert_eq!(tp.grouping, 0);
+		assert_eq!(groups_to_mask(&[3, 3], 7), None);
+
+		// A stored grouping that no longer fits the numerator falls back to the default
+		assert!(tp.set_grouping_text("3+4"));
+		tp.signature.0 = 6;
+		assert_eq!(tp.grouping_text(), "3+3");
+	}
+
+	fn tick_kinds(tp: TimingPoint) -> Vec<char> {
+		let measure = tp.signature.0 as f64 * tp.ms_per_beat();
+
+		beat_ticks(&[tp], 1, 0., measure - 1., 60000.)
+			.into_iter()
+			.map(|(_, snap)| match snap {
+				SnapDivision::Downbeat => 'D',
+				SnapDivision::Beat => 'B',
+				SnapDivision::Pulse => 'p',
+				_ => '?',
+			})
+			.collect()
+	}
+
+	#[test]
+	fn compound_beat_ticks() {
+		assert_eq!(tick_kinds(signature(6, 8)), ['D', 'p', 'p', 'B', 'p', 'p']);
+		assert_eq!(
+			tick_kinds(signature(7, 8)),
+			['D', 'p', 'B', 'p', 'B', 'p', 'p']
+		);
+
+		let mut tp = signature(7, 8);
+		assert!(tp.set_grouping_text("3+2+2"));
+		assert_eq!(tick_kinds(tp), ['D', 'p', 'p', 'B', 'p', 'B', 'p']);
+	}
+
+	fn sections(offsets: &[f64]) -> Vec<TimingPoint> {
+		offsets
+			.iter()
+			.map(|&offset| TimingPoint::new(offset, 120.))
+			.collect()
+	}
+
+	#[test]
+	fn resnap_after_bpm_edit() {
+		let mut timing_points = sections(&[0., 2000., 5000.]);
+		timing_points[0].bpm = 100.;
+
+		let moved = resnap_following(&mut timing_points, 0, ResnapMode::Beat);
+		assert_eq!(moved.len(), 2);
+		assert_eq!(moved[0].0.offset, 2000.);
+		assert_close(timing_points[1].offset, 1800.);
+		assert_close(timing_points[2].offset, 4800.);
+		assert!(
+			moved
+				.iter()
+				.zip(&timing_points[1..])
+				.all(|((_, after), tp)| after == tp)
+		);
+
+		let mut timing_points = sections(&[0., 2000., 5000.]);
+		timing_points[0].bpm = 100.;
+		resnap_following(&mut timing_points, 0, ResnapMode::Measure);
+		assert_close(timing_points[1].offset, 2400.);
+		assert_close(timing_points[2].offset, 5400.);
+	}
+
+	#[test]
+	fn resnap_leaves_whole_sections_alone() {
+		let mut timing_points = sections(&[0., 2000., 5000.]);
+
+		assert!(resnap_following(&mut timing_points, 0, ResnapMode::Beat).is_empty());
+		assert!(resnap_following(&mut timing_points, 0, ResnapMode::Measure).is_empty());
+		assert!(resnap_following(&mut timing_points, 2, ResnapMode::Beat).is_empty());
+
+		timing_points[0].bpm = 100.;
+		assert!(resnap_following(&mut timing_points, 0, ResnapMode::Off).is_empty());
+	}
+
+	#[test]
+	fn resnap_ramps_by_mean_tempo() {
+		let [tp, mut next] = ramp(TempoRamp::Linear);
+		next.offset = 8900.;
+		let mut timing_points = vec![tp, next];
+
+		resnap_following(&mut timing_points, 0, ResnapMode::Beat);
+		let beats = tp.beats_at(timing_points[1].offset, Some(&timing_points[1]));
+		assert!((beats - beats.round()).abs() < 1e-6, "{}", beats);
+	}
+}
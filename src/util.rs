@@ -10,6 +10,27 @@ pub fn format_time(ms: f64) -> String {
 	format!("{:02}:{:02}.{:03}", minutes, seconds, millis)
 }
 
+const NOTE_NAMES: [&str; 12] = [
+	"C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Nearest equal-tempered note to `frequency` (A4 = 440 Hz) and the offset from it in cents
+pub fn note_name(frequency: f32) -> Option<(String, i32)> {
+	if frequency <= 0. {
+		return None;
+	}
+
+	let midi = 69. + 12. * (frequency / 440.).log2();
+	let nearest = midi.round();
+	let cents = ((midi - nearest) * 100.).round() as i32;
+
+	let nearest = nearest as i32;
+	let name = NOTE_NAMES[nearest.rem_euclid(12) as usize];
+	let octave = nearest.div_euclid(12) - 1;
+
+	Some((format!("{}{}", name, octave), cents))
+}
+
 pub fn magma_colormap(t: f32) -> Color32 {
 	let t = t.clamp(0.0, 1.0);
 
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator as _};
+use serde::{Deserialize, Serialize};
+
+use crate::audio::Channel;
+
+const BASE_BLOCK: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WaveformLane {
+	Off,
+	Below,
+	Overlay,
+}
+
+#[derive(Clone, Copy)]
+pub struct Peak {
+	pub min: f32,
+	pub max: f32,
+	mean_square: f32,
+}
+
+impl Peak {
+	const EMPTY: Self = Self {
+		min: f32::MAX,
+		max: f32::MIN,
+		mean_square: 0.,
+	};
+
+	fn from_samples(samples: impl Iterator<Item = f32>) -> Self {
+		let mut peak = Self::EMPTY;
+		let mut count = 0;
+
+		for sample in samples {
+			peak.min = peak.min.min(sample);
+			peak.max = peak.max.max(sample);
+			peak.mean_square += sample * sample;
+			count += 1;
+		}
+
+		if count > 0 {
+			peak.mean_square /= count as f32;
+		}
+
+		peak
+	}
+
+	fn merge(peaks: &[Peak]) -> Self {
+		let mut peak = Self::EMPTY;
+
+		for p in peaks {
+			peak.min = peak.min.min(p.min);
+			peak.max = peak.max.max(p.max);
+			peak.mean_square += p.mean_square;
+		}
+
+		if !peaks.is_empty() {
+			peak.mean_square /= peaks.len() as f32;
+		}
+
+		peak
+	}
+
+	pub fn rms(&self) -> f32 {
+		self.mean_square.sqrt()
+	}
+}
+
+/// Min/max peaks of a single channel, where each level
+/// aggregates twice as many frames per peak as the previous one
+struct PeakPyramid {
+	channel: Channel,
+	levels: Vec<Vec<Peak>>,
+}
+
+impl PeakPyramid {
+	fn new(samples: &[f32], channels: usize, channel: Channel) -> Self {
+		let base: Vec<_> = samples
+			.chunks(channels * BASE_BLOCK)
+			.map(|block| Peak::from_samples(block.chunks(channels).map(|f| channel.mix(f))))
+			.collect();
+
+		let mut levels = vec![base];
+
+		while levels.last().unwrap().len() > 1 {
+			let next = levels.last().unwrap().chunks(2).map(Peak::merge).collect();
+			levels.push(next);
+		}
+
+		Self { channel, levels }
+	}
+}
+
+pub struct Waveform {
+	pub hash: u64,
+	samples: Arc<Vec<f32>>,
+	channels: usize,
+	pyramids: Vec<PeakPyramid>,
+}
+
+impl Waveform {
+	pub fn new(hash: u64, samples: Arc<Vec<f32>>, channels: u16) -> Self {
+		let channels = channels as usize;
+
+		let pyramids = [
+			Channel::Mono,
+			Channel::Left,
+			Channel::Right,
+			Channel::Mid,
+			Channel::Side,
+		]
+		.into_par_iter()
+		.map(|channel| PeakPyramid::new(&samples, channels, channel))
+		.collect();
+
+		Self {
+			hash,
+			samples,
+			channels,
+			pyramids,
+		}
+	}
+
+	/// Returns one peak per pixel, or `None` for pixels outside the audio
+	pub fn peaks(
+		&self,
+		channel: Channel,
+		start_frame: f64,
+		frames_per_pixel: f64,
+		width: usize,
+	) -> Vec<Option<Peak>> {
+		let total_frames = self.samples.len() / self.channels;
+
+		let pixel_range = |x: usize| {
+			let from = (start_frame + x as f64 * frames_per_pixel).max(0.) as usize;
+			let to = (start_frame + (x + 1) as f64 * frames_per_pixel).max(0.) as usize;
+			(from.min(total_frames), to.max(from + 1).min(total_frames))
+		};
+
+		if frames_per_pixel < BASE_BLOCK as f64 {
+			return (0..width)
+				.map(|x| {
+					let (from, to) = pixel_range(x);
+					(from < to).then(|| {
+						Peak::from_samples(
+							self.samples[from * self.channels..to * self.channels]
+								.chunks(self.channels)
+								.map(|f| channel.mix(f)),
+						)
+					})
+				})
+				.collect();
+		}
+
+		let Some(pyramid) = self.pyramids.iter().find(|p| p.channel == channel) else {
+			return vec![None; width];
+		};
+
+		let level = ((frames_per_pixel / BASE_BLOCK as f64).log2().floor() as usize)
+			.min(pyramid.levels.len() - 1);
+		let block = BASE_BLOCK << level;
+		let peaks = &pyramid.levels[level];
+
+		(0..width)
+			.map(|x| {
+				let (from, to) = pixel_range(x);
+				if from >= to {
+					return None;
+				}
+
+				let from = (from / block).min(peaks.len() - 1);
+				let to = to.div_ceil(block).min(peaks.len());
+				Some(Peak::merge(&peaks[from..to]))
+			})
+			.collect()
+	}
+}